@@ -0,0 +1,112 @@
+use crate::pattern::AssetPattern;
+use crate::version::Version;
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::fs;
+use std::time::Duration;
+
+pub static DEFAULT_CONFIG: Lazy<String> = Lazy::new(|| {
+    let config_home = std::env::var("XDG_CONFIG_HOME")
+        .unwrap_or_else(|_| format!("{}/.config", std::env::var("HOME").unwrap_or_default()));
+    format!("{}/update-brave/config.toml", config_home)
+});
+
+pub static DEFAULT_CACHE_DIR: Lazy<String> = Lazy::new(|| {
+    let cache_home = std::env::var("XDG_CACHE_HOME")
+        .unwrap_or_else(|_| format!("{}/.cache", std::env::var("HOME").unwrap_or_default()));
+    format!("{}/update-brave", cache_home)
+});
+
+/// Settings read from the TOML config file. Every setting is optional.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Pinned minisign public key, base64 encoded, used to verify releases.
+    pub public_key: Option<String>,
+
+    /// Refuse to install releases without a valid signature.
+    pub require_signature: bool,
+
+    /// Number of previous versions to keep for rollback.
+    pub keep: Option<usize>,
+
+    /// Version to install instead of the latest release, e.g. "v1.58.135".
+    pub pin: Option<String>,
+
+    /// Only install releases published at least this long ago, e.g. "2d".
+    #[serde(with = "humantime_serde")]
+    pub min_age: Option<Duration>,
+
+    /// Glob or /regex/ selecting the asset to install, instead of the suffix
+    /// for the host architecture.
+    pub asset_pattern: Option<AssetPattern>,
+
+    /// Versions that must never be installed.
+    pub blocklist: Vec<Version>,
+
+    /// Pick the channel by the release title prefix instead of GitHub's
+    /// prerelease flag.
+    pub channel_by_name: bool,
+
+    /// GitHub API base URL, e.g. for a GitHub Enterprise mirror.
+    pub api_url: Option<String>,
+
+    /// Owner of the GitHub repository releases are published in.
+    pub owner: Option<String>,
+
+    /// GitHub repository releases are published in.
+    pub repo: Option<String>,
+
+    /// GitHub token, used when $GITHUB_TOKEN is not set.
+    pub token: Option<String>,
+
+    /// File containing a GitHub token, used when $GITHUB_TOKEN is not set.
+    pub token_file: Option<String>,
+
+    /// Longest total time to wait out GitHub API rate limits, e.g. "5m".
+    #[serde(with = "humantime_serde")]
+    pub retry_budget: Option<Duration>,
+
+    /// Most pages of 100 releases to search for a matching release.
+    pub max_pages: Option<u32>,
+
+    /// Longest time to wait for a connection to the download server.
+    #[serde(with = "humantime_serde")]
+    pub connect_timeout: Option<Duration>,
+
+    /// Longest time to wait for more data before retrying the download.
+    #[serde(with = "humantime_serde")]
+    pub idle_timeout: Option<Duration>,
+
+    /// Longest total time for the download including retries.
+    #[serde(with = "humantime_serde")]
+    pub download_timeout: Option<Duration>,
+
+    /// Times to retry the download after a transient failure.
+    pub download_retries: Option<u32>,
+
+    /// Number of connections to download the release over.
+    pub connections: Option<u32>,
+
+    /// Directory for cached release metadata and partial downloads.
+    pub cache_dir: Option<String>,
+}
+
+impl Config {
+    /// Loads the config file, treating a missing file as an empty config.
+    pub fn load(path: &str) -> Result<Config> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                toml::from_str(&contents).with_context(|| format!("Invalid config file {}", path))
+            }
+            Err(err) => {
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    Ok(Config::default())
+                } else {
+                    Err(err.into())
+                }
+            }
+        }
+    }
+}
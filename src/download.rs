@@ -0,0 +1,526 @@
+use crate::progress::Progress;
+use anyhow::{anyhow, Context, Result};
+use futures::{future, StreamExt};
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, RANGE};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs;
+use std::future::Future;
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_RETRIES: u32 = 3;
+pub const DEFAULT_CONNECTIONS: u32 = 1;
+
+/// Smallest byte range worth its own connection.
+const MIN_RANGE: u64 = 1024 * 1024;
+
+/// Backoff between attempts, doubling each time.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// What was being downloaded into a partial file, so a later run only resumes
+/// it if the asset is unchanged.
+#[derive(Serialize, Deserialize, Debug)]
+struct State {
+    url: String,
+    size: u64,
+    etag: Option<String>,
+}
+
+/// A release asset downloaded into the cache directory, named by its GitHub
+/// asset ID. An interrupted download is resumed with a Range request on the
+/// next run, as long as the size and ETag show the asset has not changed.
+pub struct Download {
+    path: PathBuf,
+    state_path: PathBuf,
+    url: String,
+    size: u64,
+    connect_timeout: Duration,
+    idle_timeout: Duration,
+    timeout: Option<Duration>,
+    retries: u32,
+    connections: u32,
+}
+
+impl Download {
+    pub fn new(dir: &Path, asset_id: u64, url: &str, size: u64) -> Download {
+        Download {
+            path: dir.join(asset_id.to_string()),
+            state_path: dir.join(format!("{}.json", asset_id)),
+            url: url.into(),
+            size,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            timeout: None,
+            retries: DEFAULT_RETRIES,
+            connections: DEFAULT_CONNECTIONS,
+        }
+    }
+
+    /// Longest time to wait for a connection to the server.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Download {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Longest time to wait for more data before treating the connection as
+    /// stalled.
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Download {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Longest total time for the download, including retries.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Download {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Times to retry after a transient failure.
+    pub fn retries(mut self, retries: u32) -> Download {
+        self.retries = retries;
+        self
+    }
+
+    /// Returns the number of bytes an earlier run downloaded that can be
+    /// resumed from.
+    pub fn resumable(&self) -> u64 {
+        let Some(state) = self.read_state() else {
+            return 0;
+        };
+        if state.url != self.url || state.size != self.size || state.etag.is_none() {
+            return 0;
+        }
+        match fs::metadata(&self.path) {
+            Ok(metadata) if metadata.len() <= self.size => metadata.len(),
+            _ => 0,
+        }
+    }
+
+    /// Number of connections to download a fresh copy of the asset over, each
+    /// fetching its own byte range. Unlike a single stream, an interrupted
+    /// ranged download starts over.
+    pub fn connections(mut self, connections: u32) -> Download {
+        self.connections = connections;
+        self
+    }
+
+    /// Downloads the rest of the asset. Transient failures such as server
+    /// errors, dropped connections and stalls are retried, resuming from what
+    /// was downloaded so far.
+    pub async fn fetch(&self) -> Result<()> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .build()?;
+        let attempts = retry(self.retries, || self.fetch_once(&client));
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, attempts)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Download timed out after {}",
+                        humantime::format_duration(timeout)
+                    )
+                })?,
+            None => attempts.await,
+        }
+    }
+
+    /// Makes a single attempt at downloading the rest of the asset. The server
+    /// only continues the partial download if its ETag still matches,
+    /// otherwise it starts over.
+    async fn fetch_once(&self, client: &reqwest::Client) -> Result<()> {
+        let offset = self.resumable();
+        if offset > 0 && offset == self.size {
+            return Ok(());
+        }
+        if offset == 0 && self.connections > 1 {
+            let ranges = self.ranges();
+            if ranges.len() > 1 {
+                return self.fetch_ranges(client, &ranges).await;
+            }
+        }
+        let mut request = client.get(&self.url);
+        let state = self.read_state();
+        if offset > 0 {
+            if let Some(etag) = state.as_ref().and_then(|state| state.etag.as_ref()) {
+                request = request
+                    .header(RANGE, format!("bytes={}-", offset))
+                    .header(IF_RANGE, etag);
+            }
+        }
+        let response = send(request, self.idle_timeout).await?;
+        let etag = etag(&response);
+        let resumed = offset > 0
+            && response.status() == StatusCode::PARTIAL_CONTENT
+            && etag.is_some()
+            && etag == state.and_then(|state| state.etag)
+            && content_range_start(&response) == Some(offset);
+        if response.status() == StatusCode::PARTIAL_CONTENT && !resumed {
+            self.remove()?;
+            return Err(anyhow!(
+                "{} changed while resuming the download, try again",
+                self.url
+            ));
+        }
+
+        if !resumed {
+            self.write_state(&State {
+                url: self.url.clone(),
+                size: self.size,
+                etag,
+            })?;
+        }
+        let file = self.open_file(!resumed)?;
+        let start = if resumed { offset } else { 0 };
+        let total = response
+            .content_length()
+            .map_or(self.size, |len| start + len);
+        let progress = RefCell::new(Progress::new(total, start));
+        self.stream(response, &file, start, &progress).await?;
+        progress.borrow_mut().finish();
+        self.check_len(&file)
+    }
+
+    /// Splits the asset into one byte range per connection, as first and last
+    /// byte, without making ranges smaller than `MIN_RANGE`. An empty asset
+    /// has no ranges.
+    fn ranges(&self) -> Vec<(u64, u64)> {
+        if self.size == 0 {
+            return vec![];
+        }
+        let count = u64::from(self.connections)
+            .min(self.size / MIN_RANGE)
+            .max(1);
+        let len = self.size.div_ceil(count);
+        (0..count)
+            .map(|i| (i * len, ((i + 1) * len).min(self.size) - 1))
+            .collect()
+    }
+
+    /// Downloads the asset from scratch with a request per byte range. The
+    /// first request doubles as the check for range support: a server that
+    /// ignores the Range header sends the whole asset, which is then
+    /// downloaded in a single stream instead.
+    async fn fetch_ranges(&self, client: &reqwest::Client, ranges: &[(u64, u64)]) -> Result<()> {
+        self.remove()?;
+        let file = self.open_file(true)?;
+        let progress = RefCell::new(Progress::new(self.size, 0));
+
+        let (first, last) = ranges[0];
+        let request = client
+            .get(&self.url)
+            .header(RANGE, format!("bytes={}-{}", first, last));
+        let response = send(request, self.idle_timeout).await?;
+        let etag = etag(&response);
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            self.stream(response, &file, 0, &progress).await?;
+        } else {
+            let first_range = self.stream_range(response, &file, first, last, &progress);
+            let other_ranges = ranges[1..].iter().map(|&(first, last)| {
+                let mut request = client
+                    .get(&self.url)
+                    .header(RANGE, format!("bytes={}-{}", first, last));
+                if let Some(ref etag) = etag {
+                    request = request.header(IF_RANGE, etag);
+                }
+                let (file, progress) = (&file, &progress);
+                async move {
+                    let response = send(request, self.idle_timeout).await?;
+                    self.stream_range(response, file, first, last, progress)
+                        .await
+                }
+            });
+            future::try_join(first_range, future::try_join_all(other_ranges)).await?;
+        }
+        progress.borrow_mut().finish();
+        self.check_len(&file)?;
+        self.write_state(&State {
+            url: self.url.clone(),
+            size: self.size,
+            etag,
+        })?;
+        Ok(())
+    }
+
+    /// Opens the download for writing, creating the cache directory if needed.
+    fn open_file(&self, truncate: bool) -> io::Result<fs::File> {
+        fs::create_dir_all(self.path.parent().unwrap_or(Path::new(".")))?;
+        fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(truncate)
+            .open(&self.path)
+    }
+
+    fn check_len(&self, file: &fs::File) -> Result<()> {
+        let len = file.metadata()?.len();
+        if len != self.size {
+            return Err(anyhow!(
+                "Downloaded {} bytes from {}, expected {}",
+                len,
+                self.url,
+                self.size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes a 206 response for the range from byte `first` to `last` into
+    /// place.
+    async fn stream_range(
+        &self,
+        response: reqwest::Response,
+        file: &fs::File,
+        first: u64,
+        last: u64,
+        progress: &RefCell<Progress>,
+    ) -> Result<()> {
+        if response.status() != StatusCode::PARTIAL_CONTENT
+            || content_range_start(&response) != Some(first)
+        {
+            return Err(anyhow!("{} changed during the download", self.url));
+        }
+        let len = self.stream(response, file, first, progress).await?;
+        if len != last - first + 1 {
+            return Err(anyhow!(
+                "Downloaded {} bytes of range {}-{} from {}",
+                len,
+                first,
+                last,
+                self.url
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes the response body into the file starting at `offset`, returning
+    /// the number of bytes written. A stream that stalls for longer than the
+    /// idle timeout is abandoned.
+    async fn stream(
+        &self,
+        response: reqwest::Response,
+        file: &fs::File,
+        offset: u64,
+        progress: &RefCell<Progress>,
+    ) -> Result<u64> {
+        let mut written = 0;
+        let mut byte_stream = response.bytes_stream();
+        loop {
+            let next = tokio::time::timeout(self.idle_timeout, byte_stream.next())
+                .await
+                .with_context(|| {
+                    format!(
+                        "No data received for {}",
+                        humantime::format_duration(self.idle_timeout)
+                    )
+                })?;
+            let Some(item) = next else {
+                break;
+            };
+            let item = item?;
+            file.write_all_at(&item, offset + written)?;
+            written += item.len() as u64;
+            progress.borrow_mut().advance(item.len() as u64);
+        }
+        Ok(written)
+    }
+
+    pub fn open(&self) -> io::Result<fs::File> {
+        fs::File::open(&self.path)
+    }
+
+    /// Removes the downloaded file and its state.
+    pub fn remove(&self) -> io::Result<()> {
+        for path in [&self.path, &self.state_path] {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn read_state(&self) -> Option<State> {
+        let contents = fs::read_to_string(&self.state_path).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    fn write_state(&self, state: &State) -> Result<()> {
+        let dir = self.state_path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(serde_json::to_string(state)?.as_bytes())?;
+        file.persist(&self.state_path)?;
+        Ok(())
+    }
+}
+
+/// Whether a failed attempt is worth retrying: server errors, connection
+/// failures and stalled or dropped connections.
+/// Fetches a small text file, such as a checksum published alongside an
+/// asset, with the same timeouts and retries as a download.
+pub async fn fetch_text(
+    url: &str,
+    connect_timeout: Duration,
+    idle_timeout: Duration,
+    retries: u32,
+) -> Result<String> {
+    let client = reqwest::Client::builder()
+        .connect_timeout(connect_timeout)
+        .build()?;
+    retry(retries, || async {
+        let response = send(client.get(url), idle_timeout).await?;
+        let text = tokio::time::timeout(idle_timeout, response.text())
+            .await
+            .with_context(|| {
+                format!(
+                    "No data received for {}",
+                    humantime::format_duration(idle_timeout)
+                )
+            })??;
+        Ok(text)
+    })
+    .await
+}
+
+/// Makes up to `retries` more attempts after transient failures, backing off
+/// between them.
+async fn retry<T, F>(retries: u32, mut attempt: impl FnMut() -> F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let mut retried = 0;
+    loop {
+        match attempt().await {
+            Err(err) if retried < retries && is_transient(&err) => {
+                let delay = MIN_BACKOFF
+                    .saturating_mul(2u32.saturating_pow(retried))
+                    .min(MAX_BACKOFF);
+                eprintln!(
+                    "Download failed: {:#}, retrying in {}",
+                    err,
+                    humantime::format_duration(delay)
+                );
+                tokio::time::sleep(delay).await;
+                retried += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Sends the request, giving up if the server doesn't start responding
+/// within the idle timeout.
+async fn send(
+    request: reqwest::RequestBuilder,
+    idle_timeout: Duration,
+) -> Result<reqwest::Response> {
+    let response = tokio::time::timeout(idle_timeout, request.send())
+        .await
+        .with_context(|| {
+            format!(
+                "No response received for {}",
+                humantime::format_duration(idle_timeout)
+            )
+        })??;
+    Ok(response.error_for_status()?)
+}
+
+fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            return err.is_connect()
+                || err.is_timeout()
+                || err.is_body()
+                || err.is_request()
+                || err.status().is_some_and(|status| status.is_server_error());
+        }
+        if let Some(err) = cause.downcast_ref::<io::Error>() {
+            return matches!(
+                err.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::TimedOut
+            );
+        }
+        cause.is::<tokio::time::error::Elapsed>()
+    })
+}
+
+fn etag(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+/// Returns the first byte of a `Content-Range: bytes <first>-<last>/<size>`
+/// response.
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    let value = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (first, _) = value.strip_prefix("bytes ")?.split_once('-')?;
+    first.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn download(size: u64, connections: u32) -> Download {
+        Download::new(Path::new("/nonexistent"), 1, "http://localhost/a.zip", size)
+            .connections(connections)
+    }
+
+    #[test]
+    fn ranges_cover_the_asset() {
+        let size = 5 * MIN_RANGE + 3;
+        let ranges = download(size, 4).ranges();
+        assert_eq!(ranges.len(), 4);
+        assert_eq!(ranges[0].0, 0);
+        assert_eq!(ranges[3].1, size - 1);
+        for pair in ranges.windows(2) {
+            assert_eq!(pair[0].1 + 1, pair[1].0);
+        }
+    }
+
+    #[test]
+    fn ranges_are_not_smaller_than_min_range() {
+        assert_eq!(download(2 * MIN_RANGE, 8).ranges().len(), 2);
+        assert_eq!(
+            download(MIN_RANGE - 1, 8).ranges(),
+            vec![(0, MIN_RANGE - 2)]
+        );
+    }
+
+    #[test]
+    fn ranges_of_empty_asset() {
+        assert!(download(0, 1).ranges().is_empty());
+        assert!(download(0, 4).ranges().is_empty());
+    }
+
+    #[test]
+    fn parses_content_range_start() {
+        let response = |content_range: Option<&str>| {
+            let mut builder = http::Response::builder().status(206);
+            if let Some(content_range) = content_range {
+                builder = builder.header(CONTENT_RANGE, content_range);
+            }
+            reqwest::Response::from(builder.body("").unwrap())
+        };
+        assert_eq!(
+            content_range_start(&response(Some("bytes 100-199/200"))),
+            Some(100)
+        );
+        assert_eq!(content_range_start(&response(Some("bytes */200"))), None);
+        assert_eq!(content_range_start(&response(None)), None);
+    }
+}
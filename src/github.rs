@@ -0,0 +1,518 @@
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use http::header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use http::{HeaderMap, StatusCode};
+use octocrab::models::repos::Release;
+use octocrab::service::middleware::retry::RetryConfig;
+use octocrab::Octocrab;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+pub const DEFAULT_OWNER: &str = "brave";
+pub const DEFAULT_REPO: &str = "brave-browser";
+pub const DEFAULT_RETRY_BUDGET: Duration = Duration::from_secs(60);
+pub const DEFAULT_MAX_PAGES: u32 = 10;
+
+/// Releases per page, the most GitHub allows.
+const PER_PAGE: usize = 100;
+
+/// Backoff for rate limited responses that don't say when to retry.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Client for the releases of a single GitHub repository.
+pub struct GitHub {
+    octocrab: Octocrab,
+    api_url: String,
+    owner: String,
+    repo: String,
+    verbose: bool,
+    retry_budget: Duration,
+    max_pages: u32,
+    cache_dir: Option<PathBuf>,
+}
+
+/// A response body cached on disk along with the validators needed to make
+/// conditional requests for it. Answers to conditional requests don't count
+/// against the rate limit of authenticated clients.
+#[derive(Serialize, Deserialize)]
+struct CachedResponse {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+}
+
+/// A page of releases, newest first.
+pub struct ReleasePage {
+    pub releases: Vec<Release>,
+    /// The next page number, unless this is the last page or the page cap was
+    /// reached.
+    pub next: Option<u32>,
+}
+
+/// Rate limit state GitHub reports in the `x-ratelimit-*` response headers.
+#[derive(Debug)]
+pub struct RateLimit {
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+    pub reset: Option<DateTime<Utc>>,
+}
+
+impl RateLimit {
+    fn from_headers(headers: &HeaderMap) -> Option<RateLimit> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        let rate_limit = RateLimit {
+            limit: header("x-ratelimit-limit"),
+            remaining: header("x-ratelimit-remaining"),
+            reset: header("x-ratelimit-reset")
+                .and_then(|reset| Utc.timestamp_opt(reset.try_into().ok()?, 0).single()),
+        };
+        if rate_limit.limit.is_none() && rate_limit.remaining.is_none() {
+            return None;
+        }
+        Some(rate_limit)
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_unknown = |value: Option<u64>| value.map_or("?".to_string(), |v| v.to_string());
+        write!(
+            f,
+            "{}/{} requests remaining",
+            or_unknown(self.remaining),
+            or_unknown(self.limit)
+        )?;
+        if let Some(reset) = self.reset {
+            write!(f, ", resets at {}", reset.format("%Y-%m-%d %H:%M:%S UTC"))?;
+        }
+        Ok(())
+    }
+}
+
+/// An unsuccessful response from the GitHub API.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+    pub rate_limit: Option<RateLimit>,
+    pub retry_after: Option<Duration>,
+}
+
+impl ApiError {
+    /// GitHub signals both the primary and secondary rate limits with a 403
+    /// or 429 response.
+    fn is_rate_limited(&self) -> bool {
+        match self.status {
+            StatusCode::TOO_MANY_REQUESTS => true,
+            StatusCode::FORBIDDEN => {
+                self.retry_after.is_some()
+                    || self.rate_limit.as_ref().and_then(|r| r.remaining) == Some(0)
+                    || self.message.to_lowercase().contains("rate limit")
+            }
+            _ => false,
+        }
+    }
+
+    /// How long to wait before retrying, honouring `Retry-After` and then
+    /// `X-RateLimit-Reset`, and otherwise backing off exponentially.
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        if let Some(retry_after) = self.retry_after {
+            return retry_after + jitter(MIN_BACKOFF);
+        }
+        let reset = self
+            .rate_limit
+            .as_ref()
+            .filter(|rate_limit| rate_limit.remaining == Some(0))
+            .and_then(|rate_limit| rate_limit.reset);
+        if let Some(reset) = reset {
+            let until_reset = (reset - Utc::now()).to_std().unwrap_or_default();
+            return until_reset + jitter(MIN_BACKOFF);
+        }
+        let backoff = MIN_BACKOFF
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_BACKOFF);
+        backoff / 2 + jitter(backoff / 2)
+    }
+}
+
+/// A random duration up to `max`.
+fn jitter(max: Duration) -> Duration {
+    max.mul_f64(fastrand::f64())
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GitHub API error {}: {}", self.status, self.message)?;
+        if let Some(ref rate_limit) = self.rate_limit {
+            write!(f, " ({})", rate_limit)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// A request that failed without a response from GitHub. octocrab's errors
+/// include a backtrace in their message, so they are converted to this as
+/// soon as they are returned.
+#[derive(Debug)]
+pub enum RequestError {
+    /// GitHub could not be reached.
+    Network(Box<dyn std::error::Error + Send + Sync>),
+    /// The request could not be made, e.g. because of an invalid API URL.
+    Other(Option<Box<dyn std::error::Error + Send + Sync>>),
+}
+
+impl From<octocrab::Error> for RequestError {
+    fn from(err: octocrab::Error) -> RequestError {
+        match err {
+            octocrab::Error::Hyper { source, .. } => RequestError::Network(Box::new(source)),
+            octocrab::Error::Service { source, .. } => RequestError::Network(source),
+            octocrab::Error::Http { source, .. } => RequestError::Other(Some(Box::new(source))),
+            octocrab::Error::Uri { source, .. } => RequestError::Other(Some(Box::new(source))),
+            octocrab::Error::UriParse { source, .. } => RequestError::Other(Some(Box::new(source))),
+            octocrab::Error::InvalidHeaderValue { source, .. } => {
+                RequestError::Other(Some(Box::new(source)))
+            }
+            octocrab::Error::Serde { source, .. } => RequestError::Other(Some(Box::new(source))),
+            octocrab::Error::Json { source, .. } => RequestError::Other(Some(Box::new(source))),
+            _ => RequestError::Other(None),
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Network(_) => f.write_str("Failed to reach GitHub"),
+            RequestError::Other(_) => f.write_str("GitHub request failed"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RequestError::Network(source) | RequestError::Other(Some(source)) => {
+                Some(source.as_ref())
+            }
+            RequestError::Other(None) => None,
+        }
+    }
+}
+
+impl GitHub {
+    pub fn new(api_url: &str, owner: &str, repo: &str, token: Option<String>) -> Result<GitHub> {
+        let mut builder = Octocrab::builder()
+            .base_uri(api_url)
+            .map_err(RequestError::from)?;
+        // Rate limited and failed requests are retried by `get`, which honours
+        // the delay GitHub asks for.
+        builder.add_retry_config(RetryConfig::None);
+        if let Some(token) = token {
+            builder = builder.personal_token(token);
+        }
+        Ok(GitHub {
+            octocrab: builder.build().map_err(RequestError::from)?,
+            api_url: api_url.to_string(),
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            verbose: false,
+            retry_budget: DEFAULT_RETRY_BUDGET,
+            max_pages: DEFAULT_MAX_PAGES,
+            cache_dir: None,
+        })
+    }
+
+    /// Print each request along with the rate limit.
+    pub fn verbose(mut self, verbose: bool) -> GitHub {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Longest total time to wait out rate limits.
+    pub fn retry_budget(mut self, retry_budget: Duration) -> GitHub {
+        self.retry_budget = retry_budget;
+        self
+    }
+
+    /// Most pages of releases to walk.
+    pub fn max_pages(mut self, max_pages: u32) -> GitHub {
+        self.max_pages = max_pages;
+        self
+    }
+
+    /// Cache responses in this directory and revalidate them with conditional
+    /// requests.
+    pub fn cache_dir(mut self, cache_dir: Option<PathBuf>) -> GitHub {
+        self.cache_dir = cache_dir;
+        self
+    }
+
+    /// Returns a page of releases, starting from page 1.
+    pub async fn releases(&self, page: u32) -> Result<ReleasePage> {
+        let path = format!(
+            "/repos/{}/{}/releases?per_page={}&page={}",
+            self.owner, self.repo, PER_PAGE, page
+        );
+        let body = self.get(&path).await?;
+        let releases: Vec<Release> = serde_json::from_str(&body)?;
+        let next = if releases.len() == PER_PAGE && page < self.max_pages {
+            Some(page + 1)
+        } else {
+            None
+        };
+        Ok(ReleasePage { releases, next })
+    }
+
+    /// Returns the release with the given tag.
+    pub async fn release_by_tag(&self, tag: &str) -> Result<Release> {
+        let path = format!("/repos/{}/{}/releases/tags/{}", self.owner, self.repo, tag);
+        let body = self.get(&path).await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Sends a GET request, returning the response body or an `ApiError`.
+    /// Rate limited requests are retried until waiting any longer would exceed
+    /// the retry budget.
+    async fn get(&self, path: &str) -> Result<String> {
+        let start = Instant::now();
+        let mut attempt = 0;
+        loop {
+            let err = match self.try_get(path).await? {
+                Ok(body) => return Ok(body),
+                Err(err) => err,
+            };
+            if !err.is_rate_limited() {
+                return Err(err.into());
+            }
+            let delay = Duration::from_secs_f64(err.retry_delay(attempt).as_secs_f64().ceil());
+            attempt += 1;
+            if start.elapsed() + delay > self.retry_budget {
+                return Err(anyhow::Error::new(err).context(format!(
+                    "Giving up after {} attempts, retrying in {} would exceed the retry budget of {}",
+                    attempt,
+                    humantime::format_duration(delay),
+                    humantime::format_duration(self.retry_budget)
+                )));
+            }
+            eprintln!(
+                "Rate limited by GitHub, retrying in {}",
+                humantime::format_duration(delay)
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Sends a single GET request. Unsuccessful responses are returned as the
+    /// inner error so they can be retried.
+    async fn try_get(&self, path: &str) -> Result<Result<String, ApiError>> {
+        let cached = self.read_cache(path);
+        let mut headers = HeaderMap::new();
+        if let Some(ref cached) = cached {
+            if let Some(ref etag) = cached.etag {
+                headers.insert(IF_NONE_MATCH, HeaderValue::from_str(etag)?);
+            }
+            if let Some(ref last_modified) = cached.last_modified {
+                headers.insert(IF_MODIFIED_SINCE, HeaderValue::from_str(last_modified)?);
+            }
+        }
+        let response = self
+            .octocrab
+            ._get_with_headers(path, Some(headers))
+            .await
+            .map_err(RequestError::from)?;
+        let status = response.status();
+        let rate_limit = RateLimit::from_headers(response.headers());
+        let retry_after = response
+            .headers()
+            .get("retry-after")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_secs);
+        if self.verbose {
+            match rate_limit {
+                Some(ref rate_limit) => eprintln!("GET {}: {} ({})", path, status, rate_limit),
+                None => eprintln!("GET {}: {}", path, status),
+            }
+        }
+        if status == StatusCode::NOT_MODIFIED {
+            if let Some(cached) = cached {
+                return Ok(Ok(cached.body));
+            }
+        }
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(ToString::to_string)
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let body = self
+            .octocrab
+            .body_to_string(response)
+            .await
+            .map_err(RequestError::from)?;
+        if !status.is_success() {
+            let message = serde_json::from_str::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|json| json["message"].as_str().map(ToString::to_string))
+                .unwrap_or(body);
+            return Ok(Err(ApiError {
+                status,
+                message,
+                rate_limit,
+                retry_after,
+            }));
+        }
+        if etag.is_some() || last_modified.is_some() {
+            let cached = CachedResponse {
+                etag,
+                last_modified,
+                body,
+            };
+            if let Err(err) = self.write_cache(path, &cached) {
+                if self.verbose {
+                    eprintln!("Failed to cache {}: {:#}", path, err);
+                }
+            }
+            return Ok(Ok(cached.body));
+        }
+        Ok(Ok(body))
+    }
+
+    /// Cache files are named by a hash of the full URL, so different API
+    /// servers never share entries.
+    fn cache_path(&self, path: &str) -> Option<PathBuf> {
+        let cache_dir = self.cache_dir.as_ref()?;
+        let key = Sha256::digest(format!("{}{}", self.api_url, path));
+        Some(cache_dir.join("api").join(format!("{:x}.json", key)))
+    }
+
+    /// Returns the cached response, treating an unreadable cache as empty.
+    fn read_cache(&self, path: &str) -> Option<CachedResponse> {
+        let contents = fs::read_to_string(self.cache_path(path)?).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    fn write_cache(&self, path: &str, cached: &CachedResponse) -> Result<()> {
+        let Some(cache_path) = self.cache_path(path) else {
+            return Ok(());
+        };
+        let Some(dir) = cache_path.parent() else {
+            return Ok(());
+        };
+        fs::create_dir_all(dir)?;
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(serde_json::to_string(cached)?.as_bytes())?;
+        file.persist(cache_path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn api_error(status: StatusCode, message: &str) -> ApiError {
+        ApiError {
+            status,
+            message: message.to_string(),
+            rate_limit: None,
+            retry_after: None,
+        }
+    }
+
+    #[test]
+    fn parses_rate_limit_headers() {
+        let rate_limit = RateLimit::from_headers(&headers(&[
+            ("x-ratelimit-limit", "60"),
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "1695000000"),
+        ]))
+        .unwrap();
+        assert_eq!(rate_limit.limit, Some(60));
+        assert_eq!(rate_limit.remaining, Some(0));
+        assert_eq!(rate_limit.reset, Utc.timestamp_opt(1695000000, 0).single());
+    }
+
+    #[test]
+    fn ignores_missing_rate_limit_headers() {
+        assert!(RateLimit::from_headers(&headers(&[])).is_none());
+        assert!(
+            RateLimit::from_headers(&headers(&[("x-ratelimit-reset", "1695000000")])).is_none()
+        );
+        assert!(RateLimit::from_headers(&headers(&[("x-ratelimit-remaining", "nope")])).is_none());
+    }
+
+    #[test]
+    fn detects_rate_limits() {
+        assert!(api_error(StatusCode::TOO_MANY_REQUESTS, "").is_rate_limited());
+        assert!(api_error(StatusCode::FORBIDDEN, "API rate limit exceeded").is_rate_limited());
+        assert!(!api_error(StatusCode::FORBIDDEN, "Resource not accessible").is_rate_limited());
+        assert!(!api_error(StatusCode::NOT_FOUND, "Not Found").is_rate_limited());
+
+        let mut err = api_error(StatusCode::FORBIDDEN, "");
+        err.retry_after = Some(Duration::from_secs(1));
+        assert!(err.is_rate_limited());
+
+        let mut err = api_error(StatusCode::FORBIDDEN, "");
+        err.rate_limit = RateLimit::from_headers(&headers(&[("x-ratelimit-remaining", "0")]));
+        assert!(err.is_rate_limited());
+    }
+
+    #[test]
+    fn retries_after_retry_after() {
+        let mut err = api_error(StatusCode::TOO_MANY_REQUESTS, "");
+        err.retry_after = Some(Duration::from_secs(30));
+        let delay = err.retry_delay(0);
+        assert!(delay >= Duration::from_secs(30));
+        assert!(delay <= Duration::from_secs(30) + MIN_BACKOFF);
+    }
+
+    #[test]
+    fn retries_after_rate_limit_reset() {
+        let mut err = api_error(StatusCode::FORBIDDEN, "");
+        err.rate_limit = Some(RateLimit {
+            limit: Some(60),
+            remaining: Some(0),
+            reset: Some(Utc::now() + chrono::Duration::seconds(20)),
+        });
+        let delay = err.retry_delay(0);
+        assert!(delay >= Duration::from_secs(19));
+        assert!(delay <= Duration::from_secs(20) + MIN_BACKOFF);
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let err = api_error(StatusCode::TOO_MANY_REQUESTS, "");
+        for attempt in 0..3 {
+            let backoff = MIN_BACKOFF * 2u32.pow(attempt);
+            let delay = err.retry_delay(attempt);
+            assert!(delay >= backoff / 2 && delay <= backoff);
+        }
+        assert!(err.retry_delay(100) <= MAX_BACKOFF);
+    }
+}
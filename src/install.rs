@@ -0,0 +1,494 @@
+use anyhow::{anyhow, Context, Result};
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File in each version directory recording when it was installed, as a
+/// number that increases with every install. Version directories without it,
+/// such as a migrated legacy install, count as installed before all others.
+const INSTALL_ORDER: &str = ".install-order";
+
+/// Installed versions live in `<target>.versions/<version>`, and `<target>`
+/// itself is a symlink to the active one. Switching versions replaces the
+/// symlink with a single rename, so `<target>` always points at a complete
+/// installation.
+pub fn versions_dir(target: &str) -> PathBuf {
+    PathBuf::from(format!("{}.versions", target))
+}
+
+/// Returns the version directory `<target>` currently points at, or `None` if
+/// nothing is installed or `<target>` is a plain directory from before
+/// versioned installs.
+pub fn current_version_dir(target: &str) -> Result<Option<PathBuf>> {
+    match fs::read_link(target) {
+        Ok(link) => Ok(Some(match Path::new(target).parent() {
+            Some(parent) => parent.join(link),
+            None => link,
+        })),
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound | io::ErrorKind::InvalidInput => Ok(None),
+            _ => Err(err.into()),
+        },
+    }
+}
+
+/// Extracts the archive into a new version directory named `version`, writes
+/// the version marker into it and makes it the active installation.
+pub fn install(
+    target: &str,
+    version: &str,
+    archive: &mut zip::ZipArchive<fs::File>,
+    marker: &str,
+) -> Result<()> {
+    let versions = versions_dir(target);
+    fs::create_dir_all(&versions)?;
+    let version_dir = versions.join(version);
+    if current_version_dir(target)?.as_deref() == Some(version_dir.as_path()) {
+        return Err(anyhow!("{} is already the active installation", version));
+    }
+
+    let order = installed_versions(target)?
+        .first()
+        .map_or(0, |(order, _)| *order)
+        + 1;
+
+    // Extract into a staging directory so a crash never leaves a partial tree
+    // under the final version name.
+    let staging = tempfile::Builder::new()
+        .prefix(".staging-")
+        .tempdir_in(&versions)?;
+    extract(archive, staging.path())?;
+    fs::write(staging.path().join("version"), marker)?;
+    fs::write(staging.path().join(INSTALL_ORDER), order.to_string())?;
+    if version_dir.exists() {
+        fs::remove_dir_all(&version_dir)?;
+    }
+    fs::rename(staging.keep(), &version_dir)?;
+
+    activate(target, &version_dir)?;
+    Ok(())
+}
+
+/// Returns the installed version directories, most recently installed first.
+pub fn list_versions(target: &str) -> Result<Vec<PathBuf>> {
+    Ok(installed_versions(target)?
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect())
+}
+
+/// Returns the installed version directories along with their install order,
+/// most recently installed first.
+fn installed_versions(target: &str) -> Result<Vec<(u64, PathBuf)>> {
+    let entries = match fs::read_dir(versions_dir(target)) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err.into()),
+    };
+    let mut versions = vec![];
+    for entry in entries {
+        let entry = entry?;
+        // Skip staging directories left behind by an interrupted install.
+        if entry.file_name().as_bytes().starts_with(b".") || !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let order = fs::read_to_string(entry.path().join(INSTALL_ORDER))
+            .ok()
+            .and_then(|order| order.trim().parse().ok())
+            .unwrap_or(0);
+        versions.push((order, entry.path()));
+    }
+    versions.sort_by(|a, b| b.cmp(a));
+    Ok(versions)
+}
+
+/// Removes all but the `keep` most recently installed versions, besides the
+/// active one, along with staging directories left by interrupted installs.
+pub fn prune(target: &str, keep: usize) -> Result<()> {
+    remove_staging(target)?;
+    let current = current_version_dir(target)?;
+    let inactive = list_versions(target)?
+        .into_iter()
+        .filter(|version_dir| Some(version_dir) != current.as_ref());
+    for version_dir in inactive.skip(keep) {
+        fs::remove_dir_all(&version_dir)
+            .with_context(|| format!("Removing old install {}", version_dir.display()))?;
+    }
+    Ok(())
+}
+
+fn remove_staging(target: &str) -> Result<()> {
+    let entries = match fs::read_dir(versions_dir(target)) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_name().as_bytes().starts_with(b".staging-") {
+            fs::remove_dir_all(entry.path()).with_context(|| {
+                format!("Removing staging directory {}", entry.path().display())
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Makes a retained version the active installation again, returning its
+/// directory. Without an explicit version the most recently installed
+/// inactive one is used.
+pub fn rollback(target: &str, version: Option<&str>) -> Result<PathBuf> {
+    let current = current_version_dir(target)?;
+    let versions = list_versions(target)?;
+    let version_dir = match version {
+        Some(version) => versions
+            .iter()
+            .find(|version_dir| version_dir.file_name() == Some(version.as_ref()))
+            .ok_or_else(|| {
+                let available = versions
+                    .iter()
+                    .filter_map(|version_dir| version_dir.file_name())
+                    .map(|name| name.to_string_lossy())
+                    .collect::<Vec<_>>();
+                anyhow!(
+                    "Version {} is not retained, available: {}",
+                    version,
+                    available.join(", ")
+                )
+            })?,
+        None => versions
+            .iter()
+            .find(|version_dir| Some(*version_dir) != current.as_ref())
+            .ok_or_else(|| anyhow!("No previous version to roll back to"))?,
+    };
+    if Some(version_dir) == current.as_ref() {
+        return Err(anyhow!(
+            "{} is already the active installation",
+            version_dir.display()
+        ));
+    }
+    activate(target, version_dir)?;
+    Ok(version_dir.clone())
+}
+
+/// Removes `target` and every retained version.
+pub fn uninstall(target: &str) -> Result<()> {
+    match Path::new(target).symlink_metadata() {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(target)?,
+        Ok(_) => fs::remove_file(target)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    match fs::remove_dir_all(versions_dir(target)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+fn extract(archive: &mut zip::ZipArchive<fs::File>, dest: &Path) -> Result<()> {
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let outpath = match file.enclosed_name() {
+            Some(path) => path.to_owned(),
+            None => continue,
+        };
+        let outpath = dest.join(outpath);
+
+        if (*file.name()).ends_with('/') {
+            fs::create_dir_all(&outpath)?;
+        } else {
+            if let Some(p) = outpath.parent() {
+                if !p.exists() {
+                    fs::create_dir_all(p)?;
+                }
+            }
+            let mut outfile = fs::File::create(&outpath)?;
+            io::copy(&mut file, &mut outfile)?;
+        }
+
+        if let Some(mode) = file.unix_mode() {
+            fs::set_permissions(&outpath, fs::Permissions::from_mode(mode))?;
+        }
+    }
+    Ok(())
+}
+
+/// Atomically points `target` at `version_dir`.
+///
+/// A plain directory left by an older release of this tool is exchanged with
+/// the new symlink in one step and then moved into the versions directory.
+fn activate(target: &str, version_dir: &Path) -> Result<()> {
+    let link = PathBuf::from(format!("{}.link", target));
+    if link.symlink_metadata().is_ok() {
+        fs::remove_file(&link)?;
+    }
+    // Link relative to the target's parent so the whole tree can be moved.
+    let relative = version_dir
+        .strip_prefix(Path::new(target).parent().unwrap_or(Path::new("")))
+        .unwrap_or(version_dir);
+    symlink(relative, &link)?;
+
+    let target_metadata = Path::new(target).symlink_metadata();
+    match target_metadata {
+        Ok(metadata) if metadata.is_dir() => {
+            exchange(&link, Path::new(target))?;
+            let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            let legacy = versions_dir(target).join(format!("legacy-{}", secs));
+            fs::rename(&link, &legacy)?;
+        }
+        _ => fs::rename(&link, target)?,
+    }
+    Ok(())
+}
+
+/// Swaps two paths with `renameat2(RENAME_EXCHANGE)`.
+fn exchange(a: &Path, b: &Path) -> Result<()> {
+    let a = CString::new(a.as_os_str().as_bytes())?;
+    let b = CString::new(b.as_os_str().as_bytes())?;
+    let ret = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Seek, Write};
+
+    fn archive(contents: &str) -> zip::ZipArchive<fs::File> {
+        let mut writer = zip::ZipWriter::new(tempfile::tempfile().unwrap());
+        writer
+            .start_file(
+                "brave",
+                zip::write::FileOptions::default().unix_permissions(0o755),
+            )
+            .unwrap();
+        writer.write_all(contents.as_bytes()).unwrap();
+        let mut file = writer.finish().unwrap();
+        file.rewind().unwrap();
+        zip::ZipArchive::new(file).unwrap()
+    }
+
+    fn install_version(target: &str, version: &str) {
+        install(target, version, &mut archive(version), version).unwrap();
+    }
+
+    fn names(target: &str) -> Vec<String> {
+        let mut names = list_versions(target)
+            .unwrap()
+            .iter()
+            .map(|version_dir| {
+                version_dir
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    fn active(target: &str) -> String {
+        fs::read_to_string(Path::new(target).join("version")).unwrap()
+    }
+
+    #[test]
+    fn installs_behind_relative_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("brave");
+        let target = target.to_str().unwrap();
+
+        install_version(target, "v1");
+        assert_eq!(
+            fs::read_link(target).unwrap(),
+            Path::new("brave.versions/v1")
+        );
+        assert_eq!(
+            current_version_dir(target).unwrap(),
+            Some(versions_dir(target).join("v1"))
+        );
+        assert_eq!(active(target), "v1");
+        let mode = fs::metadata(Path::new(target).join("brave"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+
+        install_version(target, "v2");
+        assert_eq!(active(target), "v2");
+        assert_eq!(names(target), ["v1", "v2"]);
+        assert!(install(target, "v2", &mut archive("v2"), "v2").is_err());
+    }
+
+    #[test]
+    fn current_version_dir_of_missing_or_plain_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("brave");
+        let target = target.to_str().unwrap();
+        assert_eq!(current_version_dir(target).unwrap(), None);
+        fs::create_dir(target).unwrap();
+        assert_eq!(current_version_dir(target).unwrap(), None);
+    }
+
+    #[test]
+    fn migrates_legacy_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("brave");
+        let target = target.to_str().unwrap();
+        fs::create_dir(target).unwrap();
+        fs::write(Path::new(target).join("version"), "legacy").unwrap();
+
+        install_version(target, "v1");
+        assert_eq!(active(target), "v1");
+        let versions = names(target);
+        assert_eq!(versions.len(), 2);
+        assert!(versions[0].starts_with("legacy-"));
+        assert_eq!(versions[1], "v1");
+        let legacy = versions_dir(target).join(&versions[0]);
+        assert_eq!(
+            fs::read_to_string(legacy.join("version")).unwrap(),
+            "legacy"
+        );
+        assert!(!Path::new(&format!("{}.link", target)).exists());
+    }
+
+    #[test]
+    fn lists_no_versions_before_install() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("brave");
+        assert!(list_versions(target.to_str().unwrap()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn prunes_inactive_versions_and_staging() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("brave");
+        let target = target.to_str().unwrap();
+        install_version(target, "v1");
+        install_version(target, "v2");
+        let staging = versions_dir(target).join(".staging-crashed");
+        fs::create_dir(&staging).unwrap();
+        fs::write(staging.join("brave"), "partial").unwrap();
+        assert_eq!(names(target), ["v1", "v2"]);
+
+        prune(target, 1).unwrap();
+        assert_eq!(names(target), ["v1", "v2"]);
+        assert!(!staging.exists());
+
+        prune(target, 0).unwrap();
+        assert_eq!(names(target), ["v2"]);
+        assert_eq!(active(target), "v2");
+    }
+
+    #[test]
+    fn orders_versions_by_install() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("brave");
+        let target = target.to_str().unwrap();
+        fs::create_dir(target).unwrap();
+        install_version(target, "v2");
+        install_version(target, "v1");
+        install_version(target, "v3");
+        // Writing into a retained version must not reorder it.
+        fs::write(versions_dir(target).join("v2").join("extra"), "").unwrap();
+
+        let order = list_versions(target)
+            .unwrap()
+            .iter()
+            .map(|version_dir| {
+                version_dir
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(order[..3], ["v3", "v1", "v2"]);
+        assert!(order[3].starts_with("legacy-"));
+
+        install_version(target, "v2");
+        assert_eq!(
+            list_versions(target).unwrap()[0],
+            versions_dir(target).join("v2")
+        );
+    }
+
+    #[test]
+    fn prunes_least_recently_installed() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("brave");
+        let target = target.to_str().unwrap();
+        fs::create_dir(target).unwrap();
+        install_version(target, "v2");
+        install_version(target, "v1");
+        install_version(target, "v3");
+        fs::write(versions_dir(target).join("v2").join("extra"), "").unwrap();
+
+        prune(target, 2).unwrap();
+        assert_eq!(names(target), ["v1", "v2", "v3"]);
+        prune(target, 1).unwrap();
+        assert_eq!(names(target), ["v1", "v3"]);
+    }
+
+    #[test]
+    fn rolls_back_to_previously_installed() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("brave");
+        let target = target.to_str().unwrap();
+        install_version(target, "v2");
+        install_version(target, "v1");
+        install_version(target, "v3");
+        fs::write(versions_dir(target).join("v2").join("extra"), "").unwrap();
+
+        rollback(target, None).unwrap();
+        assert_eq!(active(target), "v1");
+        rollback(target, None).unwrap();
+        assert_eq!(active(target), "v3");
+    }
+
+    #[test]
+    fn rolls_back_to_retained_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("brave");
+        let target = target.to_str().unwrap();
+        assert!(rollback(target, None).is_err());
+        install_version(target, "v1");
+        assert!(rollback(target, None).is_err());
+        install_version(target, "v2");
+
+        assert!(rollback(target, Some("v3")).is_err());
+        assert!(rollback(target, Some("v2")).is_err());
+        rollback(target, None).unwrap();
+        assert_eq!(active(target), "v1");
+        rollback(target, Some("v2")).unwrap();
+        assert_eq!(active(target), "v2");
+    }
+
+    #[test]
+    fn uninstalls_everything() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("brave");
+        let target = target.to_str().unwrap();
+        install_version(target, "v1");
+        install_version(target, "v2");
+        uninstall(target).unwrap();
+        assert!(Path::new(target).symlink_metadata().is_err());
+        assert!(!versions_dir(target).exists());
+        uninstall(target).unwrap();
+    }
+}
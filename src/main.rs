@@ -1,64 +1,747 @@
-use anyhow::{anyhow, Result};
-use clap::Parser;
-use futures::StreamExt;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use config::{Config, DEFAULT_CACHE_DIR, DEFAULT_CONFIG};
+use download::Download;
+use github::{GitHub, ReleasePage};
 use once_cell::sync::Lazy;
+use pattern::AssetPattern;
+use report::{Action, AssetSummary, Format, InstalledSummary, ReleaseSummary, Report};
+use sha2::{Digest, Sha256};
+use std::fmt;
 use std::fs;
-use std::io;
-use std::os::unix::fs::PermissionsExt;
+use std::io::{self, Read, Seek};
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+use version::Version;
+
+mod config;
+mod download;
+mod github;
+mod install;
+mod pattern;
+mod progress;
+mod report;
+mod version;
 
 static DEFAULT_TARGET: Lazy<String> =
     Lazy::new(|| format!("{}/usr/brave", std::env::var("HOME").unwrap_or_default(),));
 
+/// Suffix of the Brave asset built for the architecture this binary was built
+/// for, e.g. "-linux-arm64.zip".
+static DEFAULT_SUFFIX: Lazy<String> = Lazy::new(|| {
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        other => other,
+    };
+    format!("-linux-{}.zip", arch)
+});
+
+/// Number of previous versions kept for rollback when not configured.
+const DEFAULT_KEEP: usize = 2;
+
 #[derive(Parser, Debug)]
 #[clap()]
 struct Args {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
     /// Target installation directory.
-    #[structopt(long, short, default_value_t = DEFAULT_TARGET.to_string())]
+    #[structopt(
+        long,
+        short,
+        global = true,
+        default_value_t = DEFAULT_TARGET.to_string(),
+        value_parser = parse_target
+    )]
     target: String,
 
-    /// Build suffix.
-    #[structopt(long, short, default_value = "-linux-amd64.zip")]
+    /// Build suffix, defaults to the build for the host architecture.
+    #[structopt(long, short, global = true, default_value_t = DEFAULT_SUFFIX.to_string())]
     suffix: String,
+
+    /// Glob or /regex/ selecting the asset to install, instead of --suffix.
+    #[structopt(long, global = true)]
+    asset_pattern: Option<AssetPattern>,
+
+    /// Release channel to follow.
+    #[structopt(long, short, global = true, value_enum, default_value_t = Channel::Release)]
+    channel: Channel,
+
+    /// Expected SHA-256 of the release zip, instead of the published checksum.
+    #[structopt(long, global = true)]
+    sha256: Option<String>,
+
+    /// Refuse to install releases without a valid signature.
+    #[structopt(long, global = true)]
+    require_signature: bool,
+
+    /// Number of previous versions to keep for rollback.
+    #[structopt(long, global = true)]
+    keep: Option<usize>,
+
+    /// Only install releases published at least this long ago, e.g. "2d".
+    #[structopt(long, global = true, value_parser = humantime::parse_duration)]
+    min_age: Option<Duration>,
+
+    /// Pick the channel by the release title prefix, e.g. "Beta v1.59.117",
+    /// instead of GitHub's prerelease flag.
+    #[structopt(long, global = true)]
+    channel_by_name: bool,
+
+    /// Allow installing a release older than the installed one.
+    #[structopt(long, global = true)]
+    allow_downgrade: bool,
+
+    /// Output format.
+    #[structopt(long, short, global = true, value_enum, default_value_t = Format::Text)]
+    output: Format,
+
+    /// Same as the check subcommand.
+    #[structopt(long)]
+    check: bool,
+
+    /// GitHub API base URL, e.g. for a GitHub Enterprise mirror.
+    #[structopt(long, global = true)]
+    api_url: Option<String>,
+
+    /// Owner of the GitHub repository releases are published in.
+    #[structopt(long, global = true)]
+    owner: Option<String>,
+
+    /// GitHub repository releases are published in.
+    #[structopt(long, global = true)]
+    repo: Option<String>,
+
+    /// File containing a GitHub token, instead of $GITHUB_TOKEN.
+    #[structopt(long, global = true)]
+    token_file: Option<String>,
+
+    /// Longest total time to wait out GitHub API rate limits, e.g. "5m".
+    #[structopt(long, global = true, value_parser = humantime::parse_duration)]
+    retry_budget: Option<Duration>,
+
+    /// Most pages of 100 releases to search for a matching release.
+    #[structopt(long, global = true)]
+    max_pages: Option<u32>,
+
+    /// Longest time to wait for a connection to the download server, e.g. "30s".
+    #[structopt(long, global = true, value_parser = humantime::parse_duration)]
+    connect_timeout: Option<Duration>,
+
+    /// Longest time to wait for more data before retrying the download, e.g.
+    /// "1m".
+    #[structopt(long, global = true, value_parser = humantime::parse_duration)]
+    idle_timeout: Option<Duration>,
+
+    /// Longest total time for the download including retries, e.g. "1h".
+    #[structopt(long, global = true, value_parser = humantime::parse_duration)]
+    download_timeout: Option<Duration>,
+
+    /// Times to retry the download after a server error, dropped connection or
+    /// stall.
+    #[structopt(long, global = true)]
+    download_retries: Option<u32>,
+
+    /// Number of connections to download the release over, each fetching a
+    /// part of it.
+    #[structopt(long, global = true)]
+    connections: Option<u32>,
+
+    /// Always fetch release metadata instead of revalidating the cache.
+    #[structopt(long, global = true)]
+    no_cache: bool,
+
+    /// Print details such as the GitHub API rate limit.
+    #[structopt(long, short, global = true)]
+    verbose: bool,
+
+    /// Config file.
+    #[structopt(long, global = true, default_value_t = DEFAULT_CONFIG.to_string())]
+    config: String,
+}
+
+impl Args {
+    fn command_name(&self) -> &'static str {
+        match self.command {
+            None if self.check => "check",
+            None => "install",
+            Some(Command::Check) => "check",
+            Some(Command::Install { .. }) => "install",
+            Some(Command::Status) => "status",
+            Some(Command::List) => "list",
+            Some(Command::ListAssets { .. }) => "list-assets",
+            Some(Command::Uninstall) => "uninstall",
+            Some(Command::Rollback { .. }) => "rollback",
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report whether an update is available without installing it.
+    Check,
+
+    /// Install the latest release, or a specific version, if it is not
+    /// already installed (default).
+    #[clap(alias = "update")]
+    Install {
+        /// Version to install, e.g. v1.58.135.
+        #[clap(conflicts_with = "version_flag")]
+        version: Option<String>,
+
+        /// Version to install, overriding the pin in the config file.
+        #[clap(long = "version", id = "version_flag", value_name = "VERSION")]
+        version_flag: Option<String>,
+    },
+
+    /// Show the installed and latest versions.
+    Status,
+
+    /// List the releases available on the channel.
+    List,
+
+    /// List the assets of a release with their size and download count.
+    ListAssets {
+        /// Version whose assets to list, e.g. v1.58.135.
+        version: String,
+    },
+
+    /// Remove the installation and all retained versions.
+    Uninstall,
+
+    /// Switch back to a previously installed version.
+    Rollback {
+        /// Version to switch to, defaults to the most recent previous one.
+        version: Option<String>,
+    },
+}
+
+/// Process exit codes. These are documented in the readme and must remain
+/// stable for scripts that depend on them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Exit {
+    Ok = 0,
+    Error = 1,
+    Network = 3,
+    Api = 4,
+    Filesystem = 5,
+    UpdateAvailable = 10,
+}
+
+impl Exit {
+    /// Classifies an error by the first recognized cause in its chain.
+    fn from_error(err: &anyhow::Error) -> Exit {
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<github::RequestError>() {
+                return match err {
+                    github::RequestError::Network(_) => Exit::Network,
+                    github::RequestError::Other(_) => Exit::Api,
+                };
+            }
+            if cause.is::<github::ApiError>() {
+                return Exit::Api;
+            }
+            if cause.is::<reqwest::Error>() || cause.is::<tokio::time::error::Elapsed>() {
+                return Exit::Network;
+            }
+            if cause.is::<io::Error>() {
+                return Exit::Filesystem;
+            }
+        }
+        Exit::Error
+    }
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> ExitCode {
+        ExitCode::from(exit as u8)
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Channel {
+    Release,
+    Beta,
+    Nightly,
+}
+
+impl Channel {
+    /// Prefix Brave uses in the release title, e.g. "Beta v1.59.117 (Chromium ...)".
+    fn prefix(self) -> &'static str {
+        match self {
+            Channel::Release => "Release",
+            Channel::Beta => "Beta",
+            Channel::Nightly => "Nightly",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Channel> {
+        Channel::from_str(s, true).ok()
+    }
+
+    /// Returns the channel of a release according to GitHub's metadata. Only
+    /// stable releases are not marked as prereleases. GitHub can't tell Beta
+    /// and Nightly builds apart, so prereleases are Nightly if the title or
+    /// tag says so and Beta otherwise.
+    fn from_metadata(release: &octocrab::models::repos::Release) -> Channel {
+        if !release.prerelease {
+            return Channel::Release;
+        }
+        let nightly = |s: &str| s.to_lowercase().contains("nightly");
+        if release.name.as_deref().is_some_and(nightly) || nightly(&release.tag_name) {
+            Channel::Nightly
+        } else {
+            Channel::Beta
+        }
+    }
+
+    /// Returns the channel whose prefix the release title starts with.
+    fn from_title(name: &str) -> Option<Channel> {
+        Channel::value_variants()
+            .iter()
+            .copied()
+            .find(|channel| name.starts_with(channel.prefix()))
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.prefix().to_lowercase())
+    }
 }
 
 struct Release {
+    channel: Channel,
     name: String,
+    tag: String,
+    version: Option<Version>,
+    published_at: Option<DateTime<Utc>>,
     url: String,
+    asset_id: u64,
+    size: u64,
+    checksum_url: Option<String>,
+    signature_url: Option<String>,
+}
+
+impl From<&Release> for ReleaseSummary {
+    fn from(release: &Release) -> ReleaseSummary {
+        ReleaseSummary {
+            name: release.name.clone(),
+            tag: release.tag.clone(),
+            version: release.version.as_ref().map(ToString::to_string),
+            channel: release.channel.to_string(),
+            published_at: release.published_at,
+            url: release.url.clone(),
+            size: release.size,
+        }
+    }
+}
+
+/// Contents of the version marker written into the installation directory.
+struct Installed {
+    channel: Channel,
+    name: String,
+    version: Option<Version>,
+}
+
+impl Installed {
+    /// The marker has the release name, channel and version on separate
+    /// lines. Older markers may only have the name, and those were always from
+    /// the release channel with the version in the name.
+    fn parse(contents: &str) -> Installed {
+        let mut lines = contents.lines();
+        let name = lines.next().unwrap_or_default().trim().to_string();
+        let channel = lines
+            .next()
+            .and_then(|l| Channel::parse(l.trim()))
+            .unwrap_or(Channel::Release);
+        let version = lines
+            .next()
+            .and_then(|l| l.parse().ok())
+            .or_else(|| Version::find(&name));
+        Installed {
+            channel,
+            name,
+            version,
+        }
+    }
+
+    /// Whether installing `other` over this would go back to an older
+    /// version. Unknown versions are never considered downgrades.
+    fn is_newer_than(&self, other: &Installed) -> bool {
+        match (&self.version, &other.version) {
+            (Some(version), Some(other_version)) => version > other_version,
+            _ => false,
+        }
+    }
+
+    fn marker(&self) -> String {
+        match self.version {
+            Some(ref version) => format!("{}\n{}\n{}\n", self.name, self.channel, version),
+            None => format!("{}\n{}\n", self.name, self.channel),
+        }
+    }
 }
 
-async fn get_latest_release(args: &Args) -> Result<Release> {
-    let octocrab = octocrab::instance();
-    let page = octocrab
-        .repos("brave", "brave-browser")
-        .releases()
-        .list()
-        .per_page(100)
-        .send()
-        .await?;
-    for release in page {
-        if let Some(ref name) = release.name {
-            if name.starts_with("Release") {
-                for asset in release.assets {
-                    if asset.name.ends_with(&args.suffix) {
-                        return Ok(Release {
-                            name: name.trim().into(),
-                            url: asset.browser_download_url.into(),
-                        });
-                    }
+/// Installations are the same if they are on the same channel and version,
+/// regardless of the release name. The name is only compared when a version
+/// is unknown.
+impl PartialEq for Installed {
+    fn eq(&self, other: &Installed) -> bool {
+        self.channel == other.channel
+            && match (&self.version, &other.version) {
+                (Some(version), Some(other_version)) => version == other_version,
+                _ => self.name == other.name,
+            }
+    }
+}
+
+impl From<&Release> for Installed {
+    fn from(release: &Release) -> Installed {
+        Installed {
+            channel: release.channel,
+            name: release.name.clone(),
+            version: release.version.clone(),
+        }
+    }
+}
+
+impl From<&Installed> for InstalledSummary {
+    fn from(installed: &Installed) -> InstalledSummary {
+        InstalledSummary {
+            name: installed.name.clone(),
+            version: installed.version.as_ref().map(ToString::to_string),
+            channel: installed.channel.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Installed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.channel)
+    }
+}
+
+/// Returns the releases on the selected channel that have a matching asset,
+/// newest first. Pages are walked until `found` is satisfied by the releases
+/// collected so far, or the page cap is reached. If releases on the channel
+/// were found but none had a matching asset, the error lists the assets of
+/// the newest one.
+async fn get_releases(
+    args: &Args,
+    config: &Config,
+    github: &GitHub,
+    found: impl Fn(&[Release]) -> bool,
+) -> Result<Vec<Release>> {
+    let mut releases = vec![];
+    let mut unmatched = None;
+    let mut page = Some(1);
+    while let Some(number) = page {
+        let ReleasePage {
+            releases: page_releases,
+            next,
+        } = github.releases(number).await?;
+        for release in page_releases {
+            if !on_channel(args, config, &release) {
+                continue;
+            }
+            match matching_release(args, config, &release)? {
+                Some(matching) => releases.push(matching),
+                None => {
+                    unmatched.get_or_insert(release);
                 }
             }
         }
+        page = if found(&releases) { None } else { next };
     }
-    Err(anyhow!("No Release Found"))
+    if let (true, Some(release)) = (releases.is_empty(), unmatched) {
+        return Err(missing_asset(args, config, &release));
+    }
+    releases.sort_by_key(|release| std::cmp::Reverse(release.published_at));
+    Ok(releases)
 }
 
-fn get_installed_version(args: &Args) -> Result<String> {
+/// Whether a GitHub release is published on the selected channel. The
+/// channel is taken from the prerelease flag unless configured to go by the
+/// title.
+fn on_channel(args: &Args, config: &Config, release: &octocrab::models::repos::Release) -> bool {
+    if release.draft {
+        return false;
+    }
+    let channel = if args.channel_by_name || config.channel_by_name {
+        Channel::from_title(release_name(release))
+    } else {
+        Some(Channel::from_metadata(release))
+    };
+    channel == Some(args.channel)
+}
+
+/// Returns the release title, or the tag for untitled releases.
+fn release_name(release: &octocrab::models::repos::Release) -> &str {
+    release
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or(&release.tag_name)
+}
+
+/// Converts a GitHub release with a matching asset. It is an error for the
+/// asset pattern to match more than one asset.
+fn matching_release(
+    args: &Args,
+    config: &Config,
+    release: &octocrab::models::repos::Release,
+) -> Result<Option<Release>> {
+    let name = release_name(release);
+    let asset = match asset_pattern(args, config) {
+        Some(pattern) => {
+            let matches = release
+                .assets
+                .iter()
+                .filter(|asset| pattern.matches(&asset.name))
+                .collect::<Vec<_>>();
+            if matches.len() > 1 {
+                let names = matches
+                    .iter()
+                    .map(|asset| asset.name.as_str())
+                    .collect::<Vec<_>>();
+                return Err(anyhow!(
+                    "Asset pattern {} is ambiguous in {}, it matches: {}",
+                    pattern,
+                    name,
+                    names.join(", ")
+                ));
+            }
+            matches.into_iter().next()
+        }
+        None => release
+            .assets
+            .iter()
+            .find(|asset| asset.name.ends_with(&args.suffix)),
+    };
+    let Some(asset) = asset else {
+        return Ok(None);
+    };
+    let companion_url = |extension: &str| {
+        let companion_name = format!("{}.{}", asset.name, extension);
+        release
+            .assets
+            .iter()
+            .find(|asset| asset.name == companion_name)
+            .map(|asset| asset.browser_download_url.to_string())
+    };
+    Ok(Some(Release {
+        channel: args.channel,
+        name: name.into(),
+        tag: release.tag_name.clone(),
+        version: release
+            .tag_name
+            .parse()
+            .ok()
+            .or_else(|| Version::find(name)),
+        published_at: release.published_at,
+        url: asset.browser_download_url.to_string(),
+        asset_id: asset.id.into_inner(),
+        size: asset.size.try_into().unwrap_or_default(),
+        checksum_url: companion_url("sha256"),
+        signature_url: companion_url("minisig"),
+    }))
+}
+
+fn asset_pattern<'a>(args: &'a Args, config: &'a Config) -> Option<&'a AssetPattern> {
+    args.asset_pattern
+        .as_ref()
+        .or(config.asset_pattern.as_ref())
+}
+
+fn missing_asset(
+    args: &Args,
+    config: &Config,
+    release: &octocrab::models::repos::Release,
+) -> anyhow::Error {
+    let available = release
+        .assets
+        .iter()
+        .map(|asset| asset.name.as_str())
+        .collect::<Vec<_>>();
+    let wanted = match asset_pattern(args, config) {
+        Some(pattern) => format!("matching {}", pattern),
+        None => format!("ending in {}", args.suffix),
+    };
+    anyhow!(
+        "{} has no asset {}, available: {}",
+        release_name(release),
+        wanted,
+        available.join(", ")
+    )
+}
+
+/// Returns the newest release that is not on the blocklist and has been
+/// published for at least the minimum age. Newer releases still soaking are
+/// reported as held back.
+async fn get_latest_release(
+    args: &Args,
+    config: &Config,
+    github: &GitHub,
+    report: &mut Report,
+) -> Result<Release> {
+    let min_age = args.min_age.or(config.min_age).unwrap_or_default();
+    let soak = chrono::Duration::from_std(min_age)?;
+    let now = Utc::now();
+    let old_enough = |release: &Release| match release.published_at {
+        Some(published_at) => now - published_at >= soak,
+        None => soak.is_zero(),
+    };
+    let eligible =
+        |release: &Release| old_enough(release) && !is_blocked(config, release.version.as_ref());
+    let mut releases = get_releases(args, config, github, |releases| {
+        releases.iter().any(eligible)
+    })
+    .await?;
+    let Some(latest) = releases.iter().position(eligible) else {
+        return Err(NoEligibleRelease {
+            channel: args.channel,
+            blocklist: !config.blocklist.is_empty(),
+            min_age,
+        }
+        .into());
+    };
+    for newer in &releases[..latest] {
+        if is_blocked(config, newer.version.as_ref()) {
+            report.message(format!("Skipping {}, it is on the blocklist", newer.name));
+        }
+    }
+    if let Some(newest) = releases[..latest]
+        .iter()
+        .find(|r| !is_blocked(config, r.version.as_ref()))
+    {
+        report.message(format!(
+            "Holding back {} until it is {} old",
+            newest.name,
+            humantime::format_duration(min_age)
+        ));
+        report.held_back = Some(newest.into());
+    }
+    Ok(releases.swap_remove(latest))
+}
+
+/// Whether `version` is on the blocklist. Unknown versions never are.
+fn is_blocked(config: &Config, version: Option<&Version>) -> bool {
+    version.is_some_and(|version| config.blocklist.contains(version))
+}
+
+/// No release on the channel passes the blocklist and minimum age.
+#[derive(Debug)]
+struct NoEligibleRelease {
+    channel: Channel,
+    /// Whether a blocklist is configured.
+    blocklist: bool,
+    min_age: Duration,
+}
+
+impl fmt::Display for NoEligibleRelease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No release found on the {} channel", self.channel)?;
+        let mut filters = vec![];
+        if self.blocklist {
+            filters.push("is not blocklisted".to_string());
+        }
+        if !self.min_age.is_zero() {
+            filters.push(format!(
+                "was published at least {} ago",
+                humantime::format_duration(self.min_age)
+            ));
+        }
+        if !filters.is_empty() {
+            write!(f, " that {}", filters.join(" and "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for NoEligibleRelease {}
+
+/// Looks up the GitHub release tagged `version`, with or without the leading
+/// "v".
+async fn lookup_release(
+    github: &GitHub,
+    version: &str,
+) -> Result<octocrab::models::repos::Release> {
+    let tag = version
+        .parse::<Version>()
+        .map_err(|err| anyhow!(err))?
+        .to_string();
+    github
+        .release_by_tag(&tag)
+        .await
+        .with_context(|| format!("Looking up release {}", tag))
+}
+
+/// Returns the release tagged `version` if it is on the selected channel.
+async fn get_release(
+    args: &Args,
+    config: &Config,
+    github: &GitHub,
+    version: &str,
+) -> Result<Release> {
+    let release = lookup_release(github, version).await?;
+    if !on_channel(args, config, &release) {
+        return Err(anyhow!(
+            "Release {} is not on the {} channel",
+            release.tag_name,
+            args.channel
+        ));
+    }
+    matching_release(args, config, &release)?.ok_or_else(|| missing_asset(args, config, &release))
+}
+
+/// Returns the release to install: an explicitly requested version, the
+/// version pinned in the config, or the latest release. Newer releases held
+/// back by a pin are reported.
+async fn get_target_release(
+    args: &Args,
+    config: &Config,
+    github: &GitHub,
+    report: &mut Report,
+    version: Option<&str>,
+) -> Result<Release> {
+    if let Some(version) = version {
+        return get_release(args, config, github, version).await;
+    }
+    let Some(ref pin) = config.pin else {
+        return get_latest_release(args, config, github, report).await;
+    };
+    let pinned = get_release(args, config, github, pin).await?;
+    // Newer releases are only looked up to be reported, so failing to find
+    // one must not keep the pin from being installed.
+    match get_latest_release(args, config, github, report).await {
+        Ok(latest) if latest.version > pinned.version => {
+            report.message(format!(
+                "Pinned to {}, holding back {}",
+                pinned.tag, latest.name
+            ));
+            report.held_back = Some((&latest).into());
+        }
+        Ok(_) => {}
+        Err(err) => {
+            if args.verbose {
+                eprintln!("Failed to look up newer releases: {:#}", err);
+            }
+        }
+    }
+    Ok(pinned)
+}
+
+fn get_installed_version(args: &Args) -> Result<Option<Installed>> {
     match fs::read_to_string(format!("{}/version", args.target)) {
-        Ok(contents) => Ok(contents),
+        Ok(contents) => Ok(Some(Installed::parse(&contents))),
         Err(err) => {
             if err.kind() == std::io::ErrorKind::NotFound {
-                Ok(String::new())
+                Ok(None)
             } else {
                 Err(err.into())
             }
@@ -66,55 +749,611 @@ fn get_installed_version(args: &Args) -> Result<String> {
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
-    let installed_version = get_installed_version(&args)?;
-    let latest_release = get_latest_release(&args).await?;
-    if installed_version == latest_release.name {
-        println!("No updates, already current: {}", latest_release.name);
-    } else {
-        println!(
-            "Upgrading from {} to {}",
-            installed_version, latest_release.name
+/// Returns the lowercase hex SHA-256 the downloaded zip must match.
+async fn get_expected_sha256(args: &Args, config: &Config, release: &Release) -> Result<String> {
+    if let Some(ref sha256) = args.sha256 {
+        return Ok(sha256.trim().to_lowercase());
+    }
+    let Some(ref checksum_url) = release.checksum_url else {
+        return Err(anyhow!(
+            "No checksum published for {}, pass --sha256 to verify it manually",
+            release.name
+        ));
+    };
+    // The checksum file is in sha256sum format: "<digest>  <filename>".
+    let contents = fetch_text(args, config, checksum_url).await?;
+    match contents.split_whitespace().next() {
+        Some(digest) => Ok(digest.to_lowercase()),
+        None => Err(anyhow!("Empty checksum file at {}", checksum_url)),
+    }
+}
+
+/// Downloads the release into the cache directory, resuming a download
+/// interrupted in an earlier run, and verifies its checksum. The cached copy
+/// is removed once it has been checked so a corrupt download is never
+/// resumed.
+async fn download(
+    args: &Args,
+    config: &Config,
+    report: &Report,
+    release: &Release,
+    expected_sha256: &str,
+) -> Result<fs::File> {
+    let dir = cache_dir(config).join("downloads");
+    let download = Download::new(&dir, release.asset_id, &release.url, release.size)
+        .connect_timeout(connect_timeout(args, config))
+        .idle_timeout(idle_timeout(args, config))
+        .timeout(args.download_timeout.or(config.download_timeout))
+        .retries(download_retries(args, config))
+        .connections(
+            args.connections
+                .or(config.connections)
+                .unwrap_or(download::DEFAULT_CONNECTIONS),
         );
+    let resumable = download.resumable();
+    if resumable > 0 {
+        report.message(format!(
+            "Resuming download at {} of {} bytes",
+            resumable, release.size
+        ));
+    }
+    download
+        .fetch()
+        .await
+        .with_context(|| format!("Downloading {}", release.url))?;
+    let mut file = download.open()?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    download.remove()?;
+    let actual_sha256 = format!("{:x}", hasher.finalize());
+    if actual_sha256 != expected_sha256 {
+        return Err(anyhow!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            release.url,
+            expected_sha256,
+            actual_sha256
+        ));
+    }
+    Ok(file)
+}
 
-        let mut tmp_file = tokio::fs::File::from(tempfile::tempfile()?);
-        let mut byte_stream = reqwest::get(&latest_release.url).await?.bytes_stream();
-        while let Some(item) = byte_stream.next().await {
-            tokio::io::copy(&mut item?.as_ref(), &mut tmp_file).await?;
+/// Verifies the minisign signature published alongside the release, if any.
+/// Releases without a signature are only accepted when signatures are not
+/// required.
+async fn verify_signature(
+    args: &Args,
+    config: &Config,
+    release: &Release,
+    file: &mut fs::File,
+) -> Result<()> {
+    let require_signature = args.require_signature || config.require_signature;
+    let Some(ref public_key) = config.public_key else {
+        if require_signature {
+            return Err(anyhow!(
+                "Signatures are required but no public_key is configured in {}",
+                args.config
+            ));
         }
-        let tmp_file = tmp_file.into_std().await;
-        let mut archive = zip::ZipArchive::new(tmp_file)?;
-        let target_new = PathBuf::from(format!("{}.new", &args.target));
-        for i in 0..archive.len() {
-            let mut file = archive.by_index(i)?;
-            let outpath = match file.enclosed_name() {
-                Some(path) => path.to_owned(),
-                None => continue,
-            };
-            let outpath = target_new.join(outpath);
-
-            if (*file.name()).ends_with('/') {
-                fs::create_dir_all(&outpath)?;
-            } else {
-                if let Some(p) = outpath.parent() {
-                    if !p.exists() {
-                        fs::create_dir_all(p)?;
-                    }
+        return Ok(());
+    };
+    let Some(ref signature_url) = release.signature_url else {
+        if require_signature {
+            return Err(anyhow!("No signature published for {}", release.name));
+        }
+        return Ok(());
+    };
+    let public_key = minisign_verify::PublicKey::from_base64(public_key)
+        .context("Invalid public_key in config")?;
+    let signature = fetch_text(args, config, signature_url).await?;
+    let signature = minisign_verify::Signature::decode(&signature)
+        .with_context(|| format!("Invalid signature at {}", signature_url))?;
+    let mut verifier = public_key.verify_stream(&signature)?;
+    file.rewind()?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        verifier.update(&buf[..n]);
+    }
+    file.rewind()?;
+    verifier
+        .finalize()
+        .with_context(|| format!("Signature verification failed for {}", release.url))
+}
+
+async fn check(args: &Args, config: &Config, github: &GitHub, report: &mut Report) -> Result<Exit> {
+    let installed_version = get_installed_version(args)?;
+    let latest_release = get_target_release(args, config, github, report, None).await?;
+    let latest_version = Installed::from(&latest_release);
+    report.installed = installed_version.as_ref().map(Into::into);
+    report.latest = Some((&latest_release).into());
+    match installed_version {
+        Some(installed_version) if installed_version == latest_version => {
+            report.message(format!("No updates, already current: {}", latest_version));
+            report.action = Some(Action::UpToDate);
+            Ok(Exit::Ok)
+        }
+        Some(installed_version) if installed_version.is_newer_than(&latest_version) => {
+            report.message(format!(
+                "No updates, installed {} is newer than the latest {}",
+                installed_version, latest_version
+            ));
+            report.action = Some(Action::UpToDate);
+            Ok(Exit::Ok)
+        }
+        Some(installed_version) => {
+            report.message(format!(
+                "Update available from {} to {}",
+                installed_version, latest_version
+            ));
+            report.action = Some(Action::UpdateAvailable);
+            Ok(Exit::UpdateAvailable)
+        }
+        None => {
+            report.message(format!("Not installed, latest is {}", latest_version));
+            report.action = Some(Action::UpdateAvailable);
+            Ok(Exit::UpdateAvailable)
+        }
+    }
+}
+
+async fn install(
+    args: &Args,
+    config: &Config,
+    github: &GitHub,
+    report: &mut Report,
+    version: Option<&str>,
+) -> Result<()> {
+    let installed_version = get_installed_version(args)?;
+    let release = get_target_release(args, config, github, report, version).await?;
+    if is_blocked(config, release.version.as_ref()) {
+        return Err(anyhow!(
+            "Refusing to install {}, it is on the blocklist",
+            release.name
+        ));
+    }
+    let release_version = Installed::from(&release);
+    report.installed = installed_version.as_ref().map(Into::into);
+    report.latest = Some((&release).into());
+    if installed_version.as_ref() == Some(&release_version) {
+        report.message(format!("No updates, already current: {}", release_version));
+        report.action = Some(Action::UpToDate);
+    } else {
+        let action = match installed_version {
+            Some(installed_version) if installed_version.is_newer_than(&release_version) => {
+                if !args.allow_downgrade {
+                    return Err(anyhow!(
+                        "Refusing to downgrade from {} to {}, pass --allow-downgrade to install it anyway",
+                        installed_version,
+                        release_version
+                    ));
                 }
-                let mut outfile = fs::File::create(&outpath)?;
-                io::copy(&mut file, &mut outfile)?;
+                report.message(format!(
+                    "Downgrading from {} to {}",
+                    installed_version, release_version
+                ));
+                report.warning(
+                    "Profiles used by a newer version of Chromium may not open with an older build",
+                );
+                Action::Downgraded
+            }
+            Some(installed_version) => {
+                report.message(format!(
+                    "Upgrading from {} to {}",
+                    installed_version, release_version
+                ));
+                Action::Upgraded
+            }
+            None => {
+                report.message(format!("Installing {}", release_version));
+                Action::Installed
             }
+        };
 
-            if let Some(mode) = file.unix_mode() {
-                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode)).unwrap();
+        let expected_sha256 = get_expected_sha256(args, config, &release).await?;
+        let mut tmp_file = download(args, config, report, &release, &expected_sha256).await?;
+        verify_signature(args, config, &release, &mut tmp_file).await?;
+        let mut archive = zip::ZipArchive::new(tmp_file)?;
+        install::install(
+            &args.target,
+            &release.tag,
+            &mut archive,
+            &release_version.marker(),
+        )?;
+        report.action = Some(action);
+    }
+    install::prune(&args.target, keep(args, config))?;
+    // TODO restart brave?
+    Ok(())
+}
+
+async fn status(args: &Args, config: &Config, github: &GitHub, report: &mut Report) -> Result<()> {
+    let installed_version = get_installed_version(args)?;
+    // Every release being blocklisted or still soaking doesn't keep the
+    // installation from being shown.
+    let latest_release = match get_latest_release(args, config, github, report).await {
+        Ok(latest_release) => Ok(latest_release),
+        Err(err) => match err.downcast::<NoEligibleRelease>() {
+            Ok(none) => Err(none),
+            Err(err) => return Err(err),
+        },
+    };
+    report.installed = installed_version.as_ref().map(Into::into);
+    report.latest = latest_release.as_ref().ok().map(Into::into);
+    report.retained = install::list_versions(&args.target)?
+        .iter()
+        .filter_map(|version_dir| version_dir.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect();
+    match installed_version {
+        Some(ref installed_version) => {
+            report.installed_blocked = is_blocked(config, installed_version.version.as_ref());
+            if report.installed_blocked {
+                report.message(format!(
+                    "Installed: {} (on the blocklist)",
+                    installed_version
+                ));
+            } else {
+                report.message(format!("Installed: {}", installed_version));
             }
         }
-        fs::write(target_new.join("version"), latest_release.name)?;
-        fs::remove_dir_all(&args.target)?;
-        fs::rename(&target_new, &args.target)?;
+        None => report.message("Installed: none"),
+    }
+    match latest_release {
+        Ok(ref latest_release) => {
+            report.message(format!("Latest:    {}", Installed::from(latest_release)))
+        }
+        Err(none) => report.message(format!("Latest:    none ({})", none)),
+    }
+    if let Some(ref pin) = config.pin {
+        report.message(format!("Pinned:    {}", pin));
+    }
+    report.message(format!("Target:    {}", args.target));
+    if !report.retained.is_empty() {
+        report.message(format!("Retained:  {}", report.retained.join(", ")));
+    }
+    Ok(())
+}
+
+async fn list(args: &Args, config: &Config, github: &GitHub, report: &mut Report) -> Result<()> {
+    for release in get_releases(args, config, github, |releases| !releases.is_empty()).await? {
+        let published_at = release
+            .published_at
+            .map(|published_at| published_at.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        report.message(format!(
+            "{:<12} {:<10} {}",
+            release.tag, published_at, release.name
+        ));
+        report.releases.push((&release).into());
     }
-    // TODO restart brave?
     Ok(())
 }
+
+async fn list_assets(github: &GitHub, report: &mut Report, version: &str) -> Result<()> {
+    let release = lookup_release(github, version).await?;
+    let width = release
+        .assets
+        .iter()
+        .map(|asset| asset.name.len())
+        .max()
+        .unwrap_or_default();
+    for asset in &release.assets {
+        report.message(format!(
+            "{:<width$} {:>12} {:>9}",
+            asset.name, asset.size, asset.download_count
+        ));
+        report.assets.push(AssetSummary {
+            name: asset.name.clone(),
+            size: asset.size.try_into().unwrap_or_default(),
+            download_count: asset.download_count.try_into().unwrap_or_default(),
+        });
+    }
+    Ok(())
+}
+
+fn uninstall(args: &Args, report: &mut Report) -> Result<()> {
+    let installed_version = get_installed_version(args)?;
+    match installed_version {
+        Some(ref installed_version) => {
+            report.message(format!("Uninstalling {}", installed_version))
+        }
+        None => report.message(format!("Uninstalling {}", args.target)),
+    }
+    report.installed = installed_version.as_ref().map(Into::into);
+    install::uninstall(&args.target)?;
+    report.action = Some(Action::Uninstalled);
+    Ok(())
+}
+
+fn rollback(
+    args: &Args,
+    config: &Config,
+    report: &mut Report,
+    version: Option<&str>,
+) -> Result<()> {
+    let installed_version = get_installed_version(args)?;
+    install::rollback(&args.target, version)?;
+    let rolled_back_version = get_installed_version(args)?;
+    match (installed_version, &rolled_back_version) {
+        (Some(from), Some(to)) => report.message(format!("Rolled back from {} to {}", from, to)),
+        (_, Some(to)) => report.message(format!("Rolled back to {}", to)),
+        _ => report.message("Rolled back"),
+    }
+    report.installed = rolled_back_version.as_ref().map(Into::into);
+    report.action = Some(Action::RolledBack);
+    install::prune(&args.target, keep(args, config))
+}
+
+/// Strips trailing slashes, which shell completion adds, so the sibling paths
+/// derived from the target don't end up inside it.
+fn parse_target(target: &str) -> Result<String, String> {
+    match target.trim_end_matches('/') {
+        "" => Err("The target can't be the root directory".into()),
+        target => Ok(target.to_string()),
+    }
+}
+
+fn keep(args: &Args, config: &Config) -> usize {
+    args.keep.or(config.keep).unwrap_or(DEFAULT_KEEP)
+}
+
+fn connect_timeout(args: &Args, config: &Config) -> Duration {
+    args.connect_timeout
+        .or(config.connect_timeout)
+        .unwrap_or(download::DEFAULT_CONNECT_TIMEOUT)
+}
+
+fn idle_timeout(args: &Args, config: &Config) -> Duration {
+    args.idle_timeout
+        .or(config.idle_timeout)
+        .unwrap_or(download::DEFAULT_IDLE_TIMEOUT)
+}
+
+fn download_retries(args: &Args, config: &Config) -> u32 {
+    args.download_retries
+        .or(config.download_retries)
+        .unwrap_or(download::DEFAULT_RETRIES)
+}
+
+/// Fetches a checksum or signature published alongside the release, with the
+/// same timeouts and retries as the download.
+async fn fetch_text(args: &Args, config: &Config, url: &str) -> Result<String> {
+    download::fetch_text(
+        url,
+        connect_timeout(args, config),
+        idle_timeout(args, config),
+        download_retries(args, config),
+    )
+    .await
+    .with_context(|| format!("Downloading {}", url))
+}
+
+fn github(args: &Args, config: &Config) -> Result<GitHub> {
+    let github = GitHub::new(
+        args.api_url
+            .as_deref()
+            .or(config.api_url.as_deref())
+            .unwrap_or(github::DEFAULT_API_URL),
+        args.owner
+            .as_deref()
+            .or(config.owner.as_deref())
+            .unwrap_or(github::DEFAULT_OWNER),
+        args.repo
+            .as_deref()
+            .or(config.repo.as_deref())
+            .unwrap_or(github::DEFAULT_REPO),
+        github_token(args, config)?,
+    )?
+    .verbose(args.verbose)
+    .retry_budget(
+        args.retry_budget
+            .or(config.retry_budget)
+            .unwrap_or(github::DEFAULT_RETRY_BUDGET),
+    )
+    .max_pages(
+        args.max_pages
+            .or(config.max_pages)
+            .unwrap_or(github::DEFAULT_MAX_PAGES),
+    )
+    .cache_dir(if args.no_cache {
+        None
+    } else {
+        Some(cache_dir(config))
+    });
+    Ok(github)
+}
+
+fn cache_dir(config: &Config) -> PathBuf {
+    PathBuf::from(config.cache_dir.as_deref().unwrap_or(&DEFAULT_CACHE_DIR))
+}
+
+/// Looks for a token in --token-file, $GITHUB_TOKEN, and then the config file.
+fn github_token(args: &Args, config: &Config) -> Result<Option<String>> {
+    if let Some(ref token_file) = args.token_file {
+        return read_token_file(token_file).map(Some);
+    }
+    if let Ok(token) = std::env::var("GITHUB_TOKEN") {
+        if !token.is_empty() {
+            return Ok(Some(token));
+        }
+    }
+    if let Some(ref token) = config.token {
+        return Ok(Some(token.clone()));
+    }
+    if let Some(ref token_file) = config.token_file {
+        return read_token_file(token_file).map(Some);
+    }
+    Ok(None)
+}
+
+fn read_token_file(path: &str) -> Result<String> {
+    let token = fs::read_to_string(path).with_context(|| format!("Reading token file {}", path))?;
+    Ok(token.trim().to_string())
+}
+
+async fn run(args: &Args, report: &mut Report) -> Result<Exit> {
+    let config = Config::load(&args.config)?;
+    let github = github(args, &config)?;
+    match args.command {
+        None if args.check => return check(args, &config, &github, report).await,
+        Some(_) if args.check => return Err(anyhow!("--check can't be used with a subcommand")),
+        Some(Command::Check) => return check(args, &config, &github, report).await,
+        None => install(args, &config, &github, report, None).await?,
+        Some(Command::Install {
+            ref version,
+            ref version_flag,
+        }) => {
+            let version = version.as_deref().or(version_flag.as_deref());
+            install(args, &config, &github, report, version).await?
+        }
+        Some(Command::Status) => status(args, &config, &github, report).await?,
+        Some(Command::List) => list(args, &config, &github, report).await?,
+        Some(Command::ListAssets { ref version }) => list_assets(&github, report, version).await?,
+        Some(Command::Uninstall) => uninstall(args, report)?,
+        Some(Command::Rollback { ref version }) => {
+            rollback(args, &config, report, version.as_deref())?
+        }
+    }
+    Ok(Exit::Ok)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    let start = Instant::now();
+    let mut report = Report::new(args.output, args.command_name());
+    let exit = match run(&args, &mut report).await {
+        Ok(exit) => exit,
+        Err(err) => {
+            report.error(&err);
+            Exit::from_error(&err)
+        }
+    };
+    report.finish(start.elapsed());
+    exit.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves `body` to every request on a local port and returns its URL.
+    async fn serve(body: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let _ = socket.read(&mut request).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}/brave.zip.minisig", addr)
+    }
+
+    fn release(signature_url: String) -> Release {
+        Release {
+            channel: Channel::Release,
+            name: "Release v1.58.135 (Chromium 117.0.5938.150)".into(),
+            tag: "v1.58.135".into(),
+            version: Some("v1.58.135".parse().unwrap()),
+            published_at: None,
+            url: "http://127.0.0.1/brave.zip".into(),
+            asset_id: 1,
+            size: 0,
+            checksum_url: None,
+            signature_url: Some(signature_url),
+        }
+    }
+
+    fn file_with(contents: &[u8]) -> fs::File {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(contents).unwrap();
+        file
+    }
+
+    #[tokio::test]
+    async fn verifies_signature() {
+        let contents = b"brave-browser release archive";
+        let keypair = minisign::KeyPair::generate_unencrypted_keypair().unwrap();
+        let signature = minisign::sign(None, &keypair.sk, &contents[..], None, None).unwrap();
+        let release = release(serve(signature.into_string()).await);
+        let args = Args::parse_from(["update-brave"]);
+        let config = Config {
+            public_key: Some(keypair.pk.to_base64()),
+            ..Default::default()
+        };
+
+        verify_signature(&args, &config, &release, &mut file_with(contents))
+            .await
+            .unwrap();
+        let tampered = b"brave-browser release archivE";
+        assert!(
+            verify_signature(&args, &config, &release, &mut file_with(tampered))
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn strips_trailing_slashes_from_target() {
+        let args = Args::parse_from(["update-brave", "--target", "/home/me/usr/brave//"]);
+        assert_eq!(args.target, "/home/me/usr/brave");
+        assert!(Args::try_parse_from(["update-brave", "--target", "/"]).is_err());
+    }
+    #[test]
+    fn parses_installed_marker() {
+        let installed =
+            Installed::parse("Nightly v1.60.1 (Chromium 118.0.0.0)\nnightly\nv1.60.1\n");
+        assert_eq!(installed.channel, Channel::Nightly);
+        assert_eq!(installed.name, "Nightly v1.60.1 (Chromium 118.0.0.0)");
+        assert_eq!(installed.version, Some("1.60.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn parses_legacy_installed_marker() {
+        let installed = Installed::parse("Release v1.58.135 (Chromium 117.0.5938.150)\n");
+        assert_eq!(installed.channel, Channel::Release);
+        assert_eq!(installed.version, Some("1.58.135".parse().unwrap()));
+    }
+    #[test]
+    fn compares_installed_by_channel_and_version() {
+        let installed = Installed::parse("Release v1.58.135\nrelease\nv1.58.135\n");
+        let renamed = Installed::parse("Release v1.58.135 (Chromium 117)\nrelease\nv1.58.135\n");
+        let beta = Installed::parse("Beta v1.58.135\nbeta\nv1.58.135\n");
+        let newer = Installed::parse("Release v1.58.137\nrelease\nv1.58.137\n");
+        assert!(installed == renamed);
+        assert!(installed != beta);
+        assert!(installed != newer);
+        assert!(newer.is_newer_than(&installed));
+        assert!(!installed.is_newer_than(&newer));
+    }
+
+    #[test]
+    fn compares_installed_by_name_without_version() {
+        let installed = Installed::parse("Custom build\nrelease\n");
+        assert_eq!(installed.version, None);
+        assert!(installed == Installed::parse("Custom build\nrelease\n"));
+        assert!(installed != Installed::parse("Other build\nrelease\n"));
+    }
+
+    #[test]
+    fn ignores_chromium_version_in_installed_name() {
+        let installed = Installed::parse("Nightly (Chromium 118.0.0.0)\nnightly\n");
+        let release = Installed::parse("Nightly v1.60.1\nnightly\nv1.60.1\n");
+        assert_eq!(installed.version, None);
+        assert!(!installed.is_newer_than(&release));
+    }
+}
@@ -0,0 +1,87 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// Pattern selecting the asset to install by name: a glob such as
+/// `brave-browser-*-linux-amd64.zip`, or a regex between slashes such as
+/// `/^brave-browser-[0-9.]+-linux-amd64\.zip$/`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub enum AssetPattern {
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl AssetPattern {
+    /// Globs must match the whole name, regexes anywhere in it unless
+    /// anchored.
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            AssetPattern::Glob(glob) => glob.matches(name),
+            AssetPattern::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+impl FromStr for AssetPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AssetPattern, String> {
+        match s.strip_prefix('/').and_then(|s| s.strip_suffix('/')) {
+            Some(regex) => Regex::new(regex)
+                .map(AssetPattern::Regex)
+                .map_err(|err| format!("Invalid regex {:?}: {}", regex, err)),
+            None => glob::Pattern::new(s)
+                .map(AssetPattern::Glob)
+                .map_err(|err| format!("Invalid glob {:?}: {}", s, err)),
+        }
+    }
+}
+
+impl TryFrom<String> for AssetPattern {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<AssetPattern, String> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for AssetPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetPattern::Glob(glob) => f.write_str(glob.as_str()),
+            AssetPattern::Regex(regex) => write!(f, "/{}/", regex.as_str()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_match_whole_name() {
+        let pattern: AssetPattern = "brave-browser-*-linux-amd64.zip".parse().unwrap();
+        assert!(pattern.matches("brave-browser-1.58.135-linux-amd64.zip"));
+        assert!(!pattern.matches("brave-browser-1.58.135-linux-amd64.zip.sha256"));
+        assert!(!pattern.matches("brave-browser-1.58.135-linux-arm64.zip"));
+        assert_eq!(pattern.to_string(), "brave-browser-*-linux-amd64.zip");
+    }
+
+    #[test]
+    fn regexes_match_anywhere_unless_anchored() {
+        let pattern: AssetPattern = r"/linux-amd64\.zip/".parse().unwrap();
+        assert!(pattern.matches("brave-browser-1.58.135-linux-amd64.zip"));
+        assert!(pattern.matches("brave-browser-1.58.135-linux-amd64.zip.sha256"));
+        let anchored: AssetPattern = r"/linux-amd64\.zip$/".parse().unwrap();
+        assert!(!anchored.matches("brave-browser-1.58.135-linux-amd64.zip.sha256"));
+        assert_eq!(anchored.to_string(), r"/linux-amd64\.zip$/");
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!("/brave-[/".parse::<AssetPattern>().is_err());
+        assert!("brave-[".parse::<AssetPattern>().is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+/// How often the progress bar is redrawn.
+const DRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a progress line is logged when stderr is not a terminal.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+const BAR_WIDTH: usize = 30;
+
+/// Download progress on stderr: a progress bar redrawn in place on a
+/// terminal, and a line every few seconds otherwise so logs show the download
+/// is still moving.
+pub struct Progress {
+    total: u64,
+    done: u64,
+    /// Bytes already downloaded by an earlier run, which don't count towards
+    /// the rate.
+    resumed: u64,
+    started: Instant,
+    shown: Instant,
+    tty: bool,
+    /// Whether the progress bar line needs ending.
+    drawn: bool,
+}
+
+impl Progress {
+    pub fn new(total: u64, resumed: u64) -> Progress {
+        let now = Instant::now();
+        Progress {
+            total,
+            done: resumed,
+            resumed,
+            started: now,
+            shown: now,
+            tty: io::stderr().is_terminal(),
+            drawn: false,
+        }
+    }
+
+    pub fn advance(&mut self, bytes: u64) {
+        self.done += bytes;
+        let interval = if self.tty {
+            DRAW_INTERVAL
+        } else {
+            LOG_INTERVAL
+        };
+        if self.shown.elapsed() >= interval {
+            self.show();
+        }
+    }
+
+    /// Shows the final state.
+    pub fn finish(&mut self) {
+        self.show();
+    }
+
+    fn show(&mut self) {
+        self.shown = Instant::now();
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            (self.done - self.resumed) as f64 / elapsed
+        } else {
+            0.0
+        };
+        let percent = (self.done.min(self.total) * 100)
+            .checked_div(self.total)
+            .unwrap_or_default();
+        let eta = match self.total.checked_sub(self.done) {
+            Some(remaining) if rate > 0.0 => {
+                humantime::format_duration(Duration::from_secs((remaining as f64 / rate) as u64))
+                    .to_string()
+            }
+            _ => "-".into(),
+        };
+        if self.tty {
+            let filled = percent as usize * BAR_WIDTH / 100;
+            eprint!(
+                "\r[{}{}] {} / {} {:>3}% {}/s ETA {}\x1b[K",
+                "#".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+                format_bytes(self.done),
+                format_bytes(self.total),
+                percent,
+                format_bytes(rate as u64),
+                eta
+            );
+            let _ = io::stderr().flush();
+            self.drawn = true;
+        } else {
+            eprintln!(
+                "Downloaded {} of {} ({}%) at {}/s, ETA {}",
+                format_bytes(self.done),
+                format_bytes(self.total),
+                percent,
+                format_bytes(rate as u64),
+                eta
+            );
+        }
+    }
+}
+
+/// Ends the progress bar line, also when the download failed part way.
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.drawn {
+            eprintln!();
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
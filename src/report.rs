@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// What a command did, or would do.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    UpToDate,
+    UpdateAvailable,
+    Installed,
+    Upgraded,
+    Downgraded,
+    RolledBack,
+    Uninstalled,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReleaseSummary {
+    pub name: String,
+    pub tag: String,
+    pub version: Option<String>,
+    pub channel: String,
+    pub published_at: Option<DateTime<Utc>>,
+    pub url: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InstalledSummary {
+    pub name: String,
+    pub version: Option<String>,
+    pub channel: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AssetSummary {
+    pub name: String,
+    pub size: u64,
+    pub download_count: u64,
+}
+
+/// Structured record of a command run. In text mode messages are printed as
+/// they happen, in JSON mode the whole record is printed once at the end.
+#[derive(Serialize, Debug)]
+pub struct Report {
+    #[serde(skip)]
+    format: Format,
+    pub command: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub installed: Option<InstalledSummary>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub installed_blocked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest: Option<ReleaseSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub held_back: Option<ReleaseSummary>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub releases: Vec<ReleaseSummary>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub assets: Vec<AssetSummary>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub retained: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<Action>,
+    pub duration_secs: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+impl Report {
+    pub fn new(format: Format, command: &'static str) -> Report {
+        Report {
+            format,
+            command,
+            installed: None,
+            installed_blocked: false,
+            latest: None,
+            held_back: None,
+            releases: vec![],
+            assets: vec![],
+            retained: vec![],
+            action: None,
+            duration_secs: 0.0,
+            warnings: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// Prints a human readable message in text mode.
+    pub fn message(&self, message: impl fmt::Display) {
+        if self.format == Format::Text {
+            println!("{}", message);
+        }
+    }
+
+    /// Records the warning, printing it immediately in text mode.
+    pub fn warning(&mut self, warning: impl fmt::Display) {
+        if self.format == Format::Text {
+            eprintln!("Warning: {}", warning);
+        }
+        self.warnings.push(warning.to_string());
+    }
+
+    /// Records the error, printing it immediately in text mode.
+    pub fn error(&mut self, err: &anyhow::Error) {
+        if self.format == Format::Text {
+            eprintln!("Error: {:?}", err);
+        }
+        self.errors.push(format!("{:#}", err));
+    }
+
+    /// Prints the JSON record in JSON mode.
+    pub fn finish(mut self, duration: Duration) {
+        self.duration_secs = duration.as_secs_f64();
+        if self.format == Format::Json {
+            match serde_json::to_string(&self) {
+                Ok(json) => println!("{}", json),
+                Err(err) => eprintln!("Error: {:?}", err),
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// A Brave version such as `v1.58.135`, compared numerically component by
+/// component.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "String")]
+pub struct Version(Vec<u64>);
+
+impl Version {
+    /// Finds the first version in text like "Release v1.58.135 (Chromium
+    /// 117.0.5938.150)". Only words starting with "v" are considered, so the
+    /// Chromium version is never mistaken for the Brave version.
+    pub fn find(text: &str) -> Option<Version> {
+        text.split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+            .filter(|word| word.starts_with('v'))
+            .find_map(|word| word.parse().ok())
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    /// Parses `v1.58.135` or `1.58.135`. At least two components are required
+    /// so that stray numbers aren't mistaken for versions.
+    fn from_str(s: &str) -> Result<Version, String> {
+        let parts = s
+            .trim()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| part.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("Invalid version {:?}", s))?;
+        if parts.len() < 2 {
+            return Err(format!("Invalid version {:?}", s));
+        }
+        Ok(Version(parts))
+    }
+}
+
+impl TryFrom<String> for Version {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Version, String> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = self.0.iter().map(ToString::to_string).collect::<Vec<_>>();
+        write!(f, "v{}", parts.join("."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!("v1.58.135".parse(), Ok(Version(vec![1, 58, 135])));
+        assert_eq!(" 1.58.135 ".parse(), Ok(Version(vec![1, 58, 135])));
+        assert!("1".parse::<Version>().is_err());
+        assert!("v1.58.x".parse::<Version>().is_err());
+        assert!("".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn compares_numerically() {
+        let older: Version = "v1.58.99".parse().unwrap();
+        let newer: Version = "v1.58.135".parse().unwrap();
+        assert!(newer > older);
+        assert!("v1.58.135.1".parse::<Version>().unwrap() > newer);
+    }
+
+    #[test]
+    fn finds_version_in_release_name() {
+        assert_eq!(
+            Version::find("Release v1.58.135 (Chromium 117.0.5938.150)"),
+            Some(Version(vec![1, 58, 135]))
+        );
+        assert_eq!(
+            Version::find("Nightly v1.60.1 (Chromium 118.0.0.0)"),
+            Some(Version(vec![1, 60, 1]))
+        );
+        assert_eq!(Version::find("Nightly (Chromium 118.0.0.0)"), None);
+        assert_eq!(Version::find("Release 1.58.135"), None);
+        assert_eq!(Version::find("Release v7"), None);
+    }
+
+    #[test]
+    fn displays_with_prefix() {
+        assert_eq!(Version(vec![1, 58, 135]).to_string(), "v1.58.135");
+    }
+}